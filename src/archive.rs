@@ -0,0 +1,414 @@
+use std::{
+    collections::VecDeque,
+    io::{self, Cursor, Seek, Write},
+    thread,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use axum::http::{HeaderMap, header};
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use flate2::{Compression, write::GzEncoder};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use zip::{AesMode, CompressionMethod, ZipArchive, ZipWriter, write::FileOptions};
+
+use crate::{ApiError, files::{self, DayRange}, storage::{StorageBackend, StoredFile}};
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum ArchiveFormat {
+    Zip,
+    // Uncompressed, for files that are compressed already or consumers piping into tar -x
+    Tar,
+    TarGz,
+}
+
+impl ArchiveFormat {
+    pub fn parse(value: &str) -> Result<Self, ApiError> {
+        match value {
+            "zip" => Ok(Self::Zip),
+            "tar" => Ok(Self::Tar),
+            "targz" => Ok(Self::TarGz),
+            _ => Err(ApiError::BadRequest(format!("Unknown archive format, expected zip, tar or targz: {}", value))),
+        }
+    }
+
+    // Picks the format from the Accept header, zip when there is none. Each format gets the
+    // quality of the most specific range matching it, ties go to the one listed first. Only an
+    // Accept that rules out every format is refused.
+    pub fn negotiate(headers: &HeaderMap) -> Result<Self, ApiError> {
+        let Some(accept) = headers.get(header::ACCEPT) else {
+            return Ok(Self::Zip);
+        };
+        let accept = accept.to_str().unwrap_or_default();
+        if accept.trim().is_empty() {
+            return Ok(Self::Zip);
+        }
+
+        // Media ranges in the order sent, with their quality
+        let ranges: Vec<(String, f32)> = accept.split(',')
+            .filter_map(|range| {
+                let mut parts = range.split(';');
+                let media = parts.next()?.trim().to_ascii_lowercase();
+                let quality = parts
+                    .filter_map(|param| param.trim().strip_prefix("q="))
+                    .find_map(|q| q.trim().parse::<f32>().ok())
+                    .unwrap_or(1.0);
+                (!media.is_empty()).then_some((media, quality))
+            })
+            .collect();
+
+        let preference = |format: Self| {
+            let content_type = format.content_type();
+            let (kind, _) = content_type.split_once('/').unwrap_or_default();
+            let wildcard = format!("{}/*", kind);
+            // Exact type, then type/*, then */*
+            [content_type, wildcard.as_str(), "*/*"].into_iter()
+                .find_map(|wanted| ranges.iter().position(|(media, _)| media == wanted))
+                .map(|position| (ranges[position].1, position))
+        };
+
+        [Self::Zip, Self::Tar, Self::TarGz].into_iter()
+            .filter_map(|format| preference(format).map(|(quality, position)| (format, quality, position)))
+            .filter(|(_, quality, _)| *quality > 0.0)
+            .min_by(|a, b| b.1.total_cmp(&a.1).then(a.2.cmp(&b.2)))
+            .map(|(format, _, _)| format)
+            .ok_or_else(|| ApiError::NotAcceptable(format!("{}, expected application/zip, application/x-tar or application/gzip", accept)))
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Self::Zip => "application/zip",
+            Self::Tar => "application/x-tar",
+            Self::TarGz => "application/gzip",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Zip => "zip",
+            Self::Tar => "tar",
+            Self::TarGz => "tar.gz",
+        }
+    }
+}
+
+// What goes into an archive and how it's encoded
+pub struct ArchiveOptions {
+    pub format: ArchiveFormat,
+    pub range: DayRange,
+    // Only files uploaded after this instant
+    pub since: Option<DateTime<Utc>>,
+    pub compression_method: CompressionMethod,
+    pub compression_level: Option<u8>,
+    // Zip entries compressed at once, 1 compresses them one after another while streaming
+    pub compression_threads: usize,
+    // Pins every entry's timestamp and permissions so the same files always produce identical bytes
+    pub deterministic: bool,
+    // Zip entry times are wall-clock times in it
+    pub timezone: Tz,
+    // Permissions of entries whose file has none of its own
+    pub file_mode: u32,
+    // Encrypts every zip entry with AES-256 under this password
+    pub password: Option<String>,
+    // Globs matched against the stored path, unset means no restriction
+    pub include: Option<GlobSet>,
+    pub exclude: Option<GlobSet>,
+}
+
+impl ArchiveOptions {
+    // Whether a stored file passes the day range, `since` and glob filters
+    pub fn includes(&self, file: &StoredFile) -> bool {
+        self.range.contains(&file.key)
+            && self.since.is_none_or(|since| files::uploaded_after(file, since))
+            && self.include.as_ref().is_none_or(|globs| globs.is_match(&file.key))
+            && !self.exclude.as_ref().is_some_and(|globs| globs.is_match(&file.key))
+    }
+
+    fn entry_mode(&self, file: &StoredFile) -> u32 {
+        match self.deterministic {
+            true => self.file_mode,
+            false => file.mode.unwrap_or(self.file_mode),
+        }
+    }
+
+    // Lower deflate levels trade archive size for CPU time. Level 0 wouldn't compress anything
+    // anyway, so those entries are simply stored.
+    fn compression(&self) -> (CompressionMethod, Option<i64>) {
+        match (self.compression_method, self.compression_level) {
+            (CompressionMethod::Deflated, Some(0)) => (CompressionMethod::Stored, None),
+            (CompressionMethod::Deflated, level) => (CompressionMethod::Deflated, level.map(i64::from)),
+            #[cfg(feature = "zstd")]
+            (CompressionMethod::Zstd, level) => (CompressionMethod::Zstd, level.map(i64::from)),
+            (method, _) => (method, None),
+        }
+    }
+
+    fn file_options(&self) -> FileOptions<'static, ()> {
+        let (method, level) = self.compression();
+        let file_options = FileOptions::default()
+            .compression_method(method)
+            .compression_level(level);
+
+        if self.deterministic {
+            return file_options.last_modified_time(zip::DateTime::default());
+        }
+
+        file_options
+    }
+
+    fn entry_options(&self, file: &StoredFile) -> FileOptions<'_, ()> {
+        let file_options = self.file_options();
+        let file_options = if self.deterministic {
+            file_options
+        } else {
+            file_options.last_modified_time(modified_time(file.modified, self.timezone))
+        };
+        let file_options = file_options.unix_permissions(self.entry_mode(file));
+
+        match &self.password {
+            Some(password) => file_options.with_aes_encryption(AesMode::Aes256, password),
+            None => file_options,
+        }
+    }
+
+    // Encrypted entries are finished by seeking back into them, which a streaming writer can't.
+    // Stored entries cost no CPU, spreading them over threads would only buffer them.
+    fn buffered(&self) -> bool {
+        self.password.is_some()
+            || (self.compression_threads > 1 && self.compression().0 != CompressionMethod::Stored)
+    }
+}
+
+// Zip timestamps carry no timezone and are read as wall-clock time, so they're written in the
+// configured one like day folders. Falls back to now when the file's mtime is unknown or doesn't
+// fit the DOS date range.
+fn modified_time(modified: Option<SystemTime>, timezone: Tz) -> zip::DateTime {
+    let wall_clock = |time: DateTime<Utc>| time.with_timezone(&timezone).naive_local().try_into().ok();
+    modified
+        .and_then(|modified| wall_clock(modified.into()))
+        .or_else(|| wall_clock(Utc::now()))
+        .unwrap_or_else(zip::DateTime::default_for_write)
+}
+
+// Builds one matcher from all patterns, None when there are none
+pub fn parse_globs(patterns: &[String]) -> Result<Option<GlobSet>, ApiError> {
+    if patterns.is_empty() {
+        return Ok(None);
+    }
+
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        let glob = Glob::new(pattern)
+            .map_err(|e| ApiError::BadRequest(format!("Invalid glob {}: {}", pattern, e)))?;
+        builder.add(glob);
+    }
+
+    builder.build()
+        .map(Some)
+        .map_err(|e| ApiError::BadRequest(format!("Invalid glob: {}", e)))
+}
+
+pub fn parse_compression(value: &str) -> Result<CompressionMethod, ApiError> {
+    match value {
+        "deflate" => Ok(CompressionMethod::Deflated),
+        "stored" => Ok(CompressionMethod::Stored),
+        #[cfg(feature = "zstd")]
+        "zstd" => Ok(CompressionMethod::Zstd),
+        // Servers built without the zstd feature still hand out an archive, just a deflated one
+        #[cfg(not(feature = "zstd"))]
+        "zstd" => {
+            tracing::warn!("zstd compression was requested but isn't compiled in, using deflate");
+            Ok(CompressionMethod::Deflated)
+        }
+        _ => Err(ApiError::BadRequest(format!("Unknown compression method, expected deflate, stored or zstd: {}", value))),
+    }
+}
+
+// Cache validators for an archive, derived from file metadata only
+pub struct Fingerprint {
+    pub etag: String,
+    pub last_modified: Option<SystemTime>,
+}
+
+// Cheap weak ETag for an archive, derived from the entry names, sizes and mtimes plus the
+// options that change its encoding, along with the newest mtime. File contents are never read.
+pub fn fingerprint(entries: &[(StoredFile, String)], options: &ArchiveOptions) -> Fingerprint {
+    use sha2::{Digest, Sha256};
+
+    let mut hasher = Sha256::new();
+    hasher.update(format!(
+        "{}:{:?}:{:?}:{}:{}\n",
+        options.format.extension(),
+        options.compression_method,
+        options.compression_level,
+        options.deterministic,
+        options.password.is_some()
+    ));
+
+    let mut last_modified: Option<SystemTime> = None;
+    for (file, name) in entries {
+        if let Some(modified) = file.modified {
+            last_modified = Some(last_modified.map_or(modified, |newest| newest.max(modified)));
+        }
+
+        let nanos = file.modified
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |d| d.as_nanos());
+        hasher.update(format!("{}:{}:{}:{:o}\n", name, file.size, nanos, options.entry_mode(file)));
+    }
+
+    Fingerprint {
+        etag: format!("W/\"{}\"", hex::encode(&hasher.finalize()[..16])),
+        last_modified,
+    }
+}
+
+// Archives `entries` read from `storage` into `writer` under the names they're paired with.
+// The writer doesn't need to be seekable, so this can feed a response body directly.
+pub fn write_archive<W: Write>(
+    writer: W,
+    storage: &dyn StorageBackend,
+    entries: &[(StoredFile, String)],
+    options: &ArchiveOptions,
+) -> io::Result<()> {
+    match options.format {
+        ArchiveFormat::Zip => {
+            let mut zip = ZipWriter::new_stream(writer);
+
+            if options.buffered() {
+                write_buffered(&mut zip, storage, entries, options)?;
+            } else {
+                for (file, name) in entries {
+                    let mut reader = storage.read(&file.key)?;
+                    zip.start_file(name.as_str(), options.entry_options(file)).map_err(io::Error::other)?;
+                    io::copy(&mut reader, &mut zip)?;
+                }
+            }
+
+            zip.finish().map_err(io::Error::other)?;
+        }
+        ArchiveFormat::Tar => {
+            write_tar(writer, storage, entries, options)?.flush()?;
+        }
+        ArchiveFormat::TarGz => {
+            let level = options.compression_level.map_or(Compression::default(), |l| Compression::new(l.into()));
+            write_tar(GzEncoder::new(writer, level), storage, entries, options)?.finish()?;
+        }
+    }
+
+    Ok(())
+}
+
+// Appends every entry to a tar stream, each straight from storage, and hands back the writer
+// once the end of archive marker is written
+fn write_tar<W: Write>(
+    writer: W,
+    storage: &dyn StorageBackend,
+    entries: &[(StoredFile, String)],
+    options: &ArchiveOptions,
+) -> io::Result<W> {
+    let mut tar = tar::Builder::new(writer);
+
+    for (file, name) in entries {
+        let mtime = file.modified
+            .filter(|_| !options.deterministic)
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |d| d.as_secs());
+
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Regular);
+        header.set_size(file.size);
+        header.set_mode(options.entry_mode(file));
+        header.set_mtime(mtime);
+        tar.append_data(&mut header, name, storage.read(&file.key)?)?;
+    }
+
+    tar.into_inner()
+}
+
+// Compresses up to `compression_threads` entries at once, each into a single entry zip held in
+// memory where it can be sought back into, and merges those into `zip` in the original order.
+// No more buffers than threads exist at a time, so memory stays bounded by the largest
+// compressed entries.
+fn write_buffered<W: Write + Seek>(
+    zip: &mut ZipWriter<W>,
+    storage: &dyn StorageBackend,
+    entries: &[(StoredFile, String)],
+    options: &ArchiveOptions,
+) -> io::Result<()> {
+    thread::scope(|scope| {
+        let mut in_flight = VecDeque::with_capacity(options.compression_threads);
+
+        for (file, name) in entries {
+            if in_flight.len() == options.compression_threads
+                && let Some(worker) = in_flight.pop_front()
+            {
+                merge(zip, worker)?;
+            }
+
+            in_flight.push_back(scope.spawn(move || compress(storage, file, name, options)));
+        }
+
+        while let Some(worker) = in_flight.pop_front() {
+            merge(zip, worker)?;
+        }
+
+        Ok(())
+    })
+}
+
+fn compress(
+    storage: &dyn StorageBackend,
+    file: &StoredFile,
+    name: &str,
+    options: &ArchiveOptions,
+) -> io::Result<Vec<u8>> {
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    zip.start_file(name, options.entry_options(file)).map_err(io::Error::other)?;
+    io::copy(&mut storage.read(&file.key)?, &mut zip)?;
+
+    Ok(zip.finish().map_err(io::Error::other)?.into_inner())
+}
+
+// Merging keeps the sizes in the local header of the buffered zip, a raw copy into a streaming
+// writer would flag a data descriptor it never writes
+fn merge<W: Write + Seek>(
+    zip: &mut ZipWriter<W>,
+    worker: thread::ScopedJoinHandle<'_, io::Result<Vec<u8>>>,
+) -> io::Result<()> {
+    let buffer = worker.join().map_err(|_| io::Error::other("compression thread panicked"))??;
+    let compressed = ZipArchive::new(Cursor::new(buffer)).map_err(io::Error::other)?;
+
+    zip.merge_archive(compressed).map_err(io::Error::other)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn zip_timestamps_follow_the_timezone() {
+        // 2024-05-01 23:30:10 UTC, already the next day east of Greenwich
+        let modified = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_714_606_210);
+
+        let time = modified_time(Some(modified), Tz::UTC);
+        assert_eq!((time.year(), time.month(), time.day()), (2024, 5, 1));
+        assert_eq!((time.hour(), time.minute(), time.second()), (23, 30, 10));
+
+        let time = modified_time(Some(modified), Tz::Europe__Berlin);
+        assert_eq!((time.year(), time.month(), time.day()), (2024, 5, 2));
+        assert_eq!((time.hour(), time.minute(), time.second()), (1, 30, 10));
+    }
+
+    #[test]
+    fn unknown_or_out_of_range_times_fall_back_to_now() {
+        use chrono::Datelike;
+
+        for modified in [None, Some(SystemTime::UNIX_EPOCH)] {
+            // The year may turn while the fallback is taken
+            let before = Utc::now().year();
+            let year = i32::from(modified_time(modified, Tz::UTC).year());
+            assert!((before..=Utc::now().year()).contains(&year), "{}", year);
+        }
+    }
+}
@@ -0,0 +1,116 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::Path,
+    sync::Mutex,
+};
+
+use serde_json::json;
+use sha2::{Digest, Sha256};
+
+use crate::{ApiError, AppState, auth, client::ClientIp, tenant::Tenant};
+
+// How far back from the end of an existing log the last line is looked for
+const TAIL_LEN: u64 = 1024 * 1024;
+
+struct Writer {
+    file: File,
+    // SHA-256 of the previous line, chaining the lines so edits and removals show
+    last_hash: String,
+}
+
+// Append-only JSON lines recording who uploaded, downloaded or deleted what
+pub struct AuditLog {
+    writer: Mutex<Writer>,
+    strict: bool,
+}
+
+impl AuditLog {
+    // Refuses paths inside the data dir, the log must never be served or swept by retention
+    pub fn open(path: &Path, data_dir: &Path, strict: bool) -> io::Result<Self> {
+        let parent = path.parent().filter(|p| !p.as_os_str().is_empty()).unwrap_or(Path::new("."));
+        fs::create_dir_all(parent)?;
+        fs::create_dir_all(data_dir)?;
+        if fs::canonicalize(parent)?.starts_with(fs::canonicalize(data_dir)?) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "the audit log must be outside of the data directory"));
+        }
+
+        let mut file = OpenOptions::new().create(true).read(true).append(true).open(path)?;
+        let last_hash = last_line(&mut file)?.map(|line| hash(&line)).unwrap_or_default();
+
+        Ok(Self {
+            writer: Mutex::new(Writer { file, last_hash }),
+            strict,
+        })
+    }
+
+    // A log every write to fails, as if its disk were full
+    #[cfg(test)]
+    pub fn failing(strict: bool) -> Self {
+        let file = OpenOptions::new().write(true).open("/dev/full").unwrap();
+        Self {
+            writer: Mutex::new(Writer { file, last_hash: String::new() }),
+            strict,
+        }
+    }
+
+    fn append(&self, mut entry: serde_json::Value) -> io::Result<()> {
+        let mut writer = self.writer.lock().unwrap_or_else(|e| e.into_inner());
+        entry["prev_hash"] = json!(writer.last_hash);
+
+        let line = entry.to_string();
+        let mut bytes = line.clone().into_bytes();
+        bytes.push(b'\n');
+        writer.file.write_all(&bytes)?;
+        writer.file.flush()?;
+        writer.last_hash = hash(&line);
+
+        Ok(())
+    }
+}
+
+fn hash(line: &str) -> String {
+    hex::encode(Sha256::digest(line.as_bytes()))
+}
+
+fn last_line(file: &mut File) -> io::Result<Option<String>> {
+    let len = file.metadata()?.len();
+    file.seek(SeekFrom::Start(len.saturating_sub(TAIL_LEN)))?;
+
+    let mut tail = Vec::new();
+    file.read_to_end(&mut tail)?;
+
+    Ok(String::from_utf8_lossy(&tail).lines().rfind(|line| !line.is_empty()).map(str::to_string))
+}
+
+// Records an operation on `paths`, relative to the tenant's folder. In strict mode a failed
+// write fails the request, otherwise it's only logged.
+pub fn record(
+    state: &AppState,
+    operation: &str,
+    client: &ClientIp,
+    tenant: &Tenant,
+    paths: Vec<String>,
+) -> Result<(), ApiError> {
+    let Some(audit) = &state.audit else {
+        return Ok(());
+    };
+
+    let entry = json!({
+        "timestamp": chrono::Utc::now().to_rfc3339(),
+        "operation": operation,
+        "client_ip": client.0,
+        "tenant": tenant.0,
+        "key": auth::current_key(),
+        "paths": paths
+    });
+
+    match audit.append(entry) {
+        Ok(()) => Ok(()),
+        Err(e) if audit.strict => Err(ApiError::InternalError(format!("Failed to write audit log: {}", e))),
+        Err(e) => {
+            tracing::error!(operation, error = %e, "Failed to write audit log");
+            Ok(())
+        }
+    }
+}
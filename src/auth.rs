@@ -0,0 +1,235 @@
+use std::{path::PathBuf, sync::Arc};
+
+use axum::{
+    extract::{Request, State},
+    http::{Method, header},
+    middleware::Next,
+    response::Response,
+};
+
+use serde::Deserialize;
+#[cfg(unix)]
+use tokio::signal;
+use tracing::Instrument;
+
+use crate::{ApiError, AppState, config, tenant::Tenant};
+
+// Alternative to the bearer token, for clients that can't set an Authorization header
+const API_KEY_HEADER: &str = "x-api-key";
+
+// What a token may do. Admin includes everything else.
+#[derive(Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    Upload,
+    Download,
+    Delete,
+    Admin,
+}
+
+impl Scope {
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "upload" => Some(Self::Upload),
+            "download" => Some(Self::Download),
+            "delete" => Some(Self::Delete),
+            "admin" => Some(Self::Admin),
+            _ => None,
+        }
+    }
+}
+
+// Parses a `TOKEN=SCOPE+SCOPE` pair, e.g. `abc=upload+download`
+pub fn parse_scopes(value: &str) -> Result<(String, Vec<Scope>), String> {
+    let (token, scopes) = value.split_once('=')
+        .ok_or_else(|| "expected TOKEN=SCOPE+SCOPE".to_string())?;
+    if token.is_empty() {
+        return Err("empty token".to_string());
+    }
+
+    let scopes = scopes.split('+')
+        .map(|scope| Scope::parse(scope).ok_or_else(|| format!("unknown scope {}, expected upload, download, delete or admin", scope)))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok((token.to_string(), scopes))
+}
+
+// The scope a route needs. Removing a single file is a delete, whole days at once are admin only.
+fn required_scope(method: &Method, path: &str) -> Scope {
+    if path == "/upload" || path.starts_with("/upload/") {
+        return Scope::Upload;
+    }
+
+    if path.starts_with("/admin/") {
+        return Scope::Admin;
+    }
+
+    match *method {
+        Method::DELETE if path == "/download" || path.starts_with("/download/") => Scope::Admin,
+        Method::DELETE => Scope::Delete,
+        _ => Scope::Download,
+    }
+}
+
+// A labelled credential from the config file. Each integration gets its own, so one can be
+// rotated or revoked without touching the others.
+#[derive(Clone)]
+pub struct ApiKey {
+    pub label: String,
+    pub key: String,
+    // Every scope when None
+    pub scopes: Option<Vec<Scope>>,
+    pub tenant: Option<String>,
+}
+
+// Whoever a presented token or key belongs to
+struct Credential {
+    // Only API keys have one
+    label: Option<String>,
+    tenant: Option<String>,
+    // Every scope when None
+    scopes: Option<Vec<Scope>>,
+}
+
+impl Credential {
+    fn allows(&self, required: Scope) -> bool {
+        self.scopes.as_ref()
+            .is_none_or(|scopes| scopes.iter().any(|scope| *scope == required || *scope == Scope::Admin))
+    }
+}
+
+fn authenticate(state: &AppState, provided: &str) -> Option<Credential> {
+    let matches = |expected: &str| constant_time_eq(provided.as_bytes(), expected.as_bytes());
+    let token_scopes = || state.config.token_scopes.iter()
+        .find(|(token, _)| matches(token))
+        .map(|(_, scopes)| scopes.clone());
+
+    if state.config.auth_token.as_deref().is_some_and(matches) {
+        return Some(Credential { label: None, tenant: None, scopes: token_scopes() });
+    }
+
+    if let Some((_, tenant)) = state.config.tenants.iter().find(|(token, _)| matches(token)) {
+        return Some(Credential { label: None, tenant: Some(tenant.clone()), scopes: token_scopes() });
+    }
+
+    state.api_keys().iter()
+        .find(|api_key| matches(&api_key.key))
+        .map(|api_key| Credential {
+            label: Some(api_key.label.clone()),
+            tenant: api_key.tenant.clone(),
+            scopes: api_key.scopes.clone(),
+        })
+}
+
+tokio::task_local! {
+    static KEY_LABEL: String;
+}
+
+// Label of the API key the current request authenticated with, if any
+pub fn current_key() -> Option<String> {
+    KEY_LABEL.try_with(|label| label.clone()).ok()
+}
+
+// Rejects requests without a matching bearer token or API key, or whose credential lacks the
+// route's scope. Does nothing when none are configured. Tenant tokens and keys scope the request
+// to that tenant, the plain auth token sees everything.
+pub async fn require_token(
+    State(state): State<Arc<AppState>>,
+    mut request: Request,
+    next: Next,
+) -> Result<Response, ApiError> {
+    if state.config.auth_token.is_none() && state.config.tenants.is_empty() && state.api_keys().is_empty() {
+        return Ok(next.run(request).await);
+    }
+
+    let headers = request.headers();
+    let provided = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .or_else(|| headers.get(API_KEY_HEADER).and_then(|v| v.to_str().ok()))
+        .ok_or(ApiError::Unauthorized)?;
+
+    // An unknown token is as good as none, only a known one lacking the scope is forbidden
+    let credential = authenticate(&state, provided).ok_or(ApiError::Unauthorized)?;
+    if !credential.allows(required_scope(request.method(), request.uri().path())) {
+        return Err(ApiError::Forbidden);
+    }
+
+    if let Some(tenant) = credential.tenant {
+        request.extensions_mut().insert(Tenant(Some(tenant)));
+    }
+
+    let Some(label) = credential.label else {
+        return Ok(next.run(request).await);
+    };
+    let span = tracing::info_span!("api_key", label = %label);
+    Ok(KEY_LABEL.scope(label, next.run(request)).instrument(span).await)
+}
+
+// Re-reads the API keys from the config file on SIGHUP, so revoking a key only takes removing it
+// and reloading. A file that fails to parse, or whose keys don't pass validation alongside the
+// rest of the configuration, leaves the current keys in place.
+#[cfg(unix)]
+pub async fn reload_keys_on_hangup(state: Arc<AppState>, path: PathBuf) {
+    let mut hangup = match signal::unix::signal(signal::unix::SignalKind::hangup()) {
+        Ok(hangup) => hangup,
+        Err(e) => {
+            tracing::error!(error = %e, "Failed to listen for SIGHUP, API keys won't be reloaded");
+            return;
+        }
+    };
+
+    while hangup.recv().await.is_some() {
+        match config::load_api_keys(&path).and_then(|api_keys| validate_keys(&state.config, api_keys)) {
+            Ok(api_keys) => {
+                tracing::info!(keys = api_keys.len(), "Reloaded API keys");
+                *state.api_keys.write().unwrap_or_else(|e| e.into_inner()) = api_keys;
+            }
+            Err(e) => tracing::error!(error = %e, "Failed to reload API keys"),
+        }
+    }
+}
+
+// Checks reloaded keys the way they'd have been checked at startup
+#[cfg(unix)]
+fn validate_keys(config: &config::AppConfig, api_keys: Vec<ApiKey>) -> Result<Vec<ApiKey>, String> {
+    let config = config::AppConfig { api_keys, ..config.clone() };
+    match config.validate() {
+        Ok(()) => Ok(config.api_keys),
+        Err(errors) => Err(errors.join(", ")),
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+
+    a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+// The keys are only ever reloaded on SIGHUP
+#[cfg(all(test, unix))]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+
+    fn key(label: &str, key: &str) -> ApiKey {
+        ApiKey { label: label.to_string(), key: key.to_string(), scopes: None, tenant: None }
+    }
+
+    #[test]
+    fn reloaded_keys_are_validated_against_the_rest_of_the_config() {
+        let config = crate::tests::config(Path::new("/srv/eotwsink"), &["--auth-token", "root"]);
+
+        let reloaded = validate_keys(&config, vec![key("ci", "ci-key")]).unwrap();
+        assert_eq!(reloaded.len(), 1);
+
+        let error = validate_keys(&config, vec![key("ci", "root")]).err().unwrap();
+        assert!(error.contains("more than once"), "{}", error);
+        assert!(!error.contains("root"), "{}", error);
+        assert!(validate_keys(&config, vec![key("a", "same"), key("b", "same")]).is_err());
+    }
+}
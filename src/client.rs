@@ -0,0 +1,76 @@
+use std::{
+    convert::Infallible,
+    fmt,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+};
+
+use axum::{
+    extract::{ConnectInfo, FromRequestParts},
+    http::{Extensions, HeaderMap, request::Parts},
+};
+use ipnet::IpNet;
+
+use crate::AppState;
+
+// Parses a trusted proxy, either a single address or a CIDR range
+pub fn parse_proxy(value: &str) -> Result<IpNet, String> {
+    value.parse::<IpNet>()
+        .or_else(|_| value.parse::<IpAddr>().map(IpNet::from))
+        .map_err(|_| format!("expected an IP address or CIDR range: {}", value))
+}
+
+// Address of the client behind a request. X-Forwarded-For is only believed when the socket peer
+// is a trusted proxy, and then only up to the rightmost hop that isn't trusted itself, since
+// everything left of it could have been made up by the client.
+pub fn client_ip(headers: &HeaderMap, extensions: &Extensions, trusted: &[IpNet]) -> Option<IpAddr> {
+    let peer = extensions
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip())?;
+    let is_trusted = |ip: &IpAddr| trusted.iter().any(|net| net.contains(ip));
+    if !is_trusted(&peer) {
+        return Some(peer);
+    }
+
+    let forwarded = headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .collect::<Vec<_>>();
+
+    let mut client = peer;
+    for hop in forwarded.iter().rev() {
+        // Whatever a hop reported beyond a malformed entry can't be attributed
+        let Ok(ip) = hop.trim().parse::<IpAddr>() else {
+            break;
+        };
+
+        client = ip;
+        if !is_trusted(&ip) {
+            break;
+        }
+    }
+
+    Some(client)
+}
+
+// Extracts the client address for logging, never rejects a request
+pub struct ClientIp(pub Option<IpAddr>);
+
+impl FromRequestParts<Arc<AppState>> for ClientIp {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, state: &Arc<AppState>) -> Result<Self, Self::Rejection> {
+        Ok(Self(client_ip(&parts.headers, &parts.extensions, &state.config.trusted_proxies)))
+    }
+}
+
+impl fmt::Display for ClientIp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Some(ip) => write!(f, "{}", ip),
+            None => write!(f, "unknown"),
+        }
+    }
+}
@@ -0,0 +1,729 @@
+use std::{collections::BTreeMap, net::SocketAddr, path::{Path, PathBuf}, time::Duration};
+
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use clap::{CommandFactory, FromArgMatches, Parser, ValueEnum, error::ErrorKind, parser::ValueSource};
+use ipnet::IpNet;
+use serde::{Deserialize, Deserializer, de};
+use zip::CompressionMethod;
+
+use crate::{auth::{self, ApiKey, Scope}, client, tenant};
+
+#[derive(Parser)]
+#[command(version, about = "Sink for uploaded EOTW logs")]
+pub struct Args {
+    /// TOML file with settings. Command line arguments and environment variables take precedence
+    #[arg(long, env = "EOTW_CONFIG")]
+    pub config: Option<PathBuf>,
+
+    /// Address and port to listen on
+    #[arg(long, env = "EOTW_BIND", default_value = "0.0.0.0:3000")]
+    pub bind: SocketAddr,
+
+    /// Proxies whose X-Forwarded-For is believed for rate limiting and audit logs, as addresses or
+    /// CIDR ranges. The socket peer is the client when unset
+    #[arg(long = "trusted-proxy", env = "EOTW_TRUSTED_PROXIES", value_delimiter = ',', value_parser = client::parse_proxy)]
+    pub trusted_proxies: Vec<IpNet>,
+
+    /// Directory where uploaded logs are stored. With S3 storage it only holds uploads in progress
+    #[arg(long, env = "EOTW_DATA_DIR", default_value = "/opt/eotw_data")]
+    pub data_dir: PathBuf,
+
+    /// Timezone that decides which day folder an upload lands in and how archives are named, as an
+    /// IANA name like Europe/Berlin
+    #[arg(long, env = "EOTW_TIMEZONE", default_value = "UTC")]
+    pub timezone: Tz,
+
+    /// Where uploads are kept
+    #[arg(long, env = "EOTW_STORAGE", value_enum, default_value_t = StorageKind::Local)]
+    pub storage: StorageKind,
+
+    /// Endpoint of the S3 compatible object store, e.g. https://s3.eu-central-1.amazonaws.com
+    #[arg(long, env = "EOTW_S3_ENDPOINT", required_if_eq("storage", "s3"))]
+    pub s3_endpoint: Option<String>,
+
+    /// Bucket uploads are stored in
+    #[arg(long, env = "EOTW_S3_BUCKET", required_if_eq("storage", "s3"))]
+    pub s3_bucket: Option<String>,
+
+    /// Region of the bucket
+    #[arg(long, env = "EOTW_S3_REGION", default_value = "us-east-1")]
+    pub s3_region: String,
+
+    /// Access key for the object store
+    #[arg(long, env = "EOTW_S3_ACCESS_KEY", required_if_eq("storage", "s3"))]
+    pub s3_access_key: Option<String>,
+
+    /// Secret key for the object store
+    #[arg(long, env = "EOTW_S3_SECRET_KEY", required_if_eq("storage", "s3"))]
+    pub s3_secret_key: Option<String>,
+
+    /// Store identical uploads only once and hardlink further copies to it. Local storage only
+    #[arg(long, env = "EOTW_DEDUP")]
+    pub dedup: bool,
+
+    /// Permissions of archive entries, in octal, when the storage doesn't know a file's own or a
+    /// deterministic archive is requested
+    #[arg(long, env = "EOTW_ARCHIVE_FILE_MODE", default_value = "644", value_parser = parse_mode)]
+    pub archive_file_mode: u32,
+
+    /// Maximum size of an upload request body in bytes
+    #[arg(long, env = "EOTW_MAX_UPLOAD_SIZE", default_value_t = 2 * 1024 * 1024)]
+    pub max_upload_size: usize,
+
+    /// Requests handled at once on the authenticated routes. Further requests wait for a free slot,
+    /// health checks and metrics are never held back
+    #[arg(long, env = "EOTW_MAX_CONCURRENT_REQUESTS", default_value_t = 512)]
+    pub max_concurrent_requests: usize,
+
+    /// Uploads handled at once, they hold their body in flight and are limited more strictly
+    #[arg(long, env = "EOTW_MAX_CONCURRENT_UPLOADS", default_value_t = 32)]
+    pub max_concurrent_uploads: usize,
+
+    /// Seconds a request may take until its response starts, slower ones are answered with 408.
+    /// Archive downloads are streamed after that point and aren't cut off
+    #[arg(long, env = "EOTW_REQUEST_TIMEOUT", default_value_t = 60)]
+    pub request_timeout: u64,
+
+    /// Seconds an upload's Idempotency-Key is remembered, repeating the key within that time
+    /// returns the original response instead of storing the files again
+    #[arg(long, env = "EOTW_IDEMPOTENCY_TTL", default_value_t = 24 * 60 * 60)]
+    pub idempotency_ttl: u64,
+
+    /// Maximum size in bytes of a file sent in chunks through /upload/init. Each chunk is limited
+    /// by --max-upload-size like any other upload request
+    #[arg(long, env = "EOTW_MAX_RESUMABLE_UPLOAD_SIZE", default_value_t = 1024 * 1024 * 1024)]
+    pub max_resumable_upload_size: u64,
+
+    /// Seconds a resumable upload is kept without receiving data before it's discarded
+    #[arg(long, env = "EOTW_RESUMABLE_UPLOAD_TTL", default_value_t = 24 * 60 * 60)]
+    pub resumable_upload_ttl: u64,
+
+    /// Maximum number of multipart fields in a single upload request
+    #[arg(long, env = "EOTW_MAX_FILES_PER_REQUEST", default_value_t = 50)]
+    pub max_files_per_request: usize,
+
+    /// Name of the multipart field uploads are sent in. Files in any other field are rejected
+    #[arg(long, env = "EOTW_UPLOAD_FIELD", default_value = "file")]
+    pub upload_field: String,
+
+    /// Refuse uploads once the data directory would grow beyond this many bytes
+    #[arg(long, env = "EOTW_MAX_TOTAL_BYTES")]
+    pub max_total_bytes: Option<u64>,
+
+    /// Maximum uploads per minute from a single client. Unlimited when unset
+    #[arg(long, env = "EOTW_UPLOAD_RATE_LIMIT")]
+    pub upload_rate_limit: Option<u32>,
+
+    /// Deflate level for downloads, 0 stores files uncompressed and 9 compresses best but slowest
+    #[arg(long, env = "EOTW_COMPRESSION_LEVEL", value_parser = clap::value_parser!(u8).range(0..=9))]
+    pub compression_level: Option<u8>,
+
+    /// Zip entries compressed in parallel, each buffered in memory until it's written. 1 compresses
+    /// while streaming, 0 uses every core
+    #[arg(long, env = "EOTW_COMPRESSION_THREADS", default_value_t = 1)]
+    pub compression_threads: usize,
+
+    /// Seconds clients may reuse file listings, dates and stats without asking again
+    #[arg(long, env = "EOTW_LISTING_MAX_AGE", default_value_t = 10)]
+    pub listing_max_age: u64,
+
+    /// Seconds clients may reuse an archive before revalidating it against its ETag, 0 revalidates
+    /// on every use
+    #[arg(long, env = "EOTW_DOWNLOAD_MAX_AGE", default_value_t = 0)]
+    pub download_max_age: u64,
+
+    /// Report the server as degraded when less than this many bytes are free on the data volume
+    #[arg(long, env = "EOTW_MIN_FREE_BYTES", default_value_t = 100 * 1024 * 1024)]
+    pub min_free_bytes: u64,
+
+    /// Bearer token required for uploads and downloads. Authentication is disabled when unset
+    #[arg(long, env = "EOTW_AUTH_TOKEN")]
+    pub auth_token: Option<String>,
+
+    /// Tokens that authenticate as a tenant, as TOKEN=TENANT pairs. Each tenant only sees its own
+    /// folder below the data dir
+    #[arg(long = "tenant", env = "EOTW_TENANTS", value_delimiter = ',', value_parser = tenant::parse_mapping)]
+    pub tenants: Vec<(String, String)>,
+
+    /// Limits tokens to some of upload, download, delete and admin, as TOKEN=SCOPE+SCOPE pairs.
+    /// Tokens without an entry may do everything. Deleting a whole day requires admin
+    #[arg(long = "token-scope", env = "EOTW_TOKEN_SCOPES", value_delimiter = ',', value_parser = auth::parse_scopes)]
+    pub token_scopes: Vec<(String, Vec<Scope>)>,
+
+    /// File that every upload, download and delete is appended to as a JSON line. Has to be
+    /// outside of the data dir
+    #[arg(long, env = "EOTW_AUDIT_LOG")]
+    pub audit_log: Option<PathBuf>,
+
+    /// Fail requests whose audit log entry can't be written instead of only logging the error
+    #[arg(long, env = "EOTW_AUDIT_STRICT")]
+    pub audit_strict: bool,
+
+    /// Delete day folders older than this many days. Logs are kept forever when unset
+    #[arg(long, env = "EOTW_RETENTION_DAYS")]
+    pub retention_days: Option<u32>,
+
+    /// Pack day folders that fall out of retention into DATE.tar.gz files in this directory
+    /// instead of deleting them. Has to be outside of the data dir
+    #[arg(long, env = "EOTW_RETENTION_ARCHIVE_DIR")]
+    pub retention_archive_dir: Option<PathBuf>,
+
+    /// Seconds between retention sweeps
+    #[arg(long, env = "EOTW_RETENTION_INTERVAL", default_value_t = 3600)]
+    pub retention_interval: u64,
+
+    /// URL that receives a JSON POST describing each successful upload
+    #[arg(long, env = "EOTW_WEBHOOK_URL")]
+    pub webhook_url: Option<String>,
+
+    /// Seconds to wait for the webhook receiver before giving up
+    #[arg(long, env = "EOTW_WEBHOOK_TIMEOUT", default_value_t = 10)]
+    pub webhook_timeout: u64,
+
+    /// File the server's own log is written to instead of the console. Rotated files get the
+    /// period inserted before the extension, like server.2024-05-01.log
+    #[arg(long, env = "EOTW_LOG_FILE")]
+    pub log_file: Option<PathBuf>,
+
+    /// How often the log file is started anew
+    #[arg(long, env = "EOTW_LOG_ROTATION", value_enum, default_value_t = LogRotation::Daily)]
+    pub log_rotation: LogRotation,
+
+    /// Rotated log files to keep, older ones are deleted. All are kept when unset
+    #[arg(long, env = "EOTW_LOG_MAX_FILES")]
+    pub log_max_files: Option<usize>,
+
+    /// SQLite database indexing the stored files. The data dir is walked instead when unset
+    #[cfg(feature = "sqlite")]
+    #[arg(long, env = "EOTW_INDEX_PATH")]
+    pub index_path: Option<PathBuf>,
+}
+
+fn parse_mode(value: &str) -> Result<u32, String> {
+    u32::from_str_radix(value.trim_start_matches("0o"), 8)
+        .ok()
+        .filter(|mode| *mode <= 0o777)
+        .ok_or_else(|| format!("expected octal permissions like 644: {}", value))
+}
+
+// Layout of the optional config file, every key may be left out
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct FileConfig {
+    server: ServerSection,
+    storage: StorageSection,
+    compression: CompressionSection,
+    auth: AuthSection,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ServerSection {
+    bind: Option<SocketAddr>,
+    max_upload_size: Option<usize>,
+    #[serde(deserialize_with = "proxies")]
+    trusted_proxies: Option<Vec<IpNet>>,
+}
+
+fn proxies<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Vec<IpNet>>, D::Error> {
+    Option::<Vec<String>>::deserialize(deserializer)?
+        .map(|proxies| proxies.iter().map(|p| client::parse_proxy(p).map_err(de::Error::custom)).collect())
+        .transpose()
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct StorageSection {
+    data_dir: Option<PathBuf>,
+    retention_days: Option<u32>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct CompressionSection {
+    level: Option<u8>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct AuthSection {
+    token: Option<String>,
+    // API keys by label, e.g. [auth.keys.ci]
+    keys: BTreeMap<String, KeySection>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct KeySection {
+    key: String,
+    // Every scope when left out
+    scopes: Option<Vec<Scope>>,
+    tenant: Option<String>,
+}
+
+impl FileConfig {
+    fn read(path: &Path) -> Result<Self, String> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        let file: Self = toml::from_str(&contents)
+            .map_err(|e| format!("Failed to parse {}: {}", path.display(), e))?;
+
+        if file.compression.level.is_some_and(|level| level > 9) {
+            return Err(format!("Invalid compression level in {}, expected 0 to 9", path.display()));
+        }
+
+        for (label, key) in &file.auth.keys {
+            if key.key.is_empty() {
+                return Err(format!("Empty API key {} in {}", label, path.display()));
+            }
+            if key.tenant.as_deref().is_some_and(|tenant| !tenant::valid_id(tenant)) {
+                return Err(format!("Invalid tenant for API key {} in {}", label, path.display()));
+            }
+        }
+
+        Ok(file)
+    }
+
+    fn api_keys(&self) -> Vec<ApiKey> {
+        self.auth.keys.iter()
+            .map(|(label, key)| ApiKey {
+                label: label.clone(),
+                key: key.key.clone(),
+                scopes: key.scopes.clone(),
+                tenant: key.tenant.clone(),
+            })
+            .collect()
+    }
+
+    // Fills in what wasn't given on the command line or through the environment
+    fn apply(self, args: &mut Args, matches: &clap::ArgMatches) {
+        let unset = |id: &str| matches!(matches.value_source(id), None | Some(ValueSource::DefaultValue));
+
+        if let Some(bind) = self.server.bind && unset("bind") {
+            args.bind = bind;
+        }
+        if let Some(max_upload_size) = self.server.max_upload_size && unset("max_upload_size") {
+            args.max_upload_size = max_upload_size;
+        }
+        if let Some(trusted_proxies) = self.server.trusted_proxies && unset("trusted_proxies") {
+            args.trusted_proxies = trusted_proxies;
+        }
+        if let Some(data_dir) = self.storage.data_dir && unset("data_dir") {
+            args.data_dir = data_dir;
+        }
+        if let Some(retention_days) = self.storage.retention_days && unset("retention_days") {
+            args.retention_days = Some(retention_days);
+        }
+        if let Some(level) = self.compression.level && unset("compression_level") {
+            args.compression_level = Some(level);
+        }
+        if let Some(token) = self.auth.token && unset("auth_token") {
+            args.auth_token = Some(token);
+        }
+    }
+}
+
+// Command line and environment first, then the config file, then the built-in defaults
+pub fn load() -> AppConfig {
+    let matches = Args::command().get_matches();
+    let mut args = Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+
+    let mut api_keys = Vec::new();
+    if let Some(path) = &args.config {
+        let file = FileConfig::read(path).unwrap_or_else(|e| Args::command().error(ErrorKind::Io, e).exit());
+        api_keys = file.api_keys();
+        file.apply(&mut args, &matches);
+    }
+
+    AppConfig { api_keys, ..AppConfig::from(args) }
+}
+
+// API keys only live in the config file, so they can be changed without a restart
+pub fn load_api_keys(path: &Path) -> Result<Vec<ApiKey>, String> {
+    FileConfig::read(path).map(|file| file.api_keys())
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+pub enum StorageKind {
+    Local,
+    S3,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum LogRotation {
+    Hourly,
+    Daily,
+    Weekly,
+    Never,
+}
+
+impl From<LogRotation> for tracing_appender::rolling::Rotation {
+    fn from(rotation: LogRotation) -> Self {
+        match rotation {
+            LogRotation::Hourly => Self::HOURLY,
+            LogRotation::Daily => Self::DAILY,
+            LogRotation::Weekly => Self::WEEKLY,
+            LogRotation::Never => Self::NEVER,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub enum StorageConfig {
+    Local,
+    S3 {
+        endpoint: String,
+        bucket: String,
+        region: String,
+        access_key: String,
+        secret_key: String,
+    },
+}
+
+#[derive(Clone)]
+pub struct AppConfig {
+    pub bind: SocketAddr,
+    pub trusted_proxies: Vec<IpNet>,
+    pub data_dir: PathBuf,
+    pub timezone: Tz,
+    pub storage: StorageConfig,
+    pub dedup: bool,
+    pub max_upload_size: usize,
+    pub max_files_per_request: usize,
+    pub max_resumable_upload_size: u64,
+    pub resumable_upload_ttl: Duration,
+    pub upload_field: String,
+    pub max_concurrent_requests: usize,
+    pub max_concurrent_uploads: usize,
+    pub request_timeout: Duration,
+    pub idempotency_ttl: Duration,
+    pub max_total_bytes: Option<u64>,
+    pub min_free_bytes: u64,
+    pub compression_method: CompressionMethod,
+    pub compression_level: Option<u8>,
+    pub compression_threads: usize,
+    pub listing_max_age: Duration,
+    pub download_max_age: Duration,
+    pub archive_file_mode: u32,
+    pub upload_rate_limit: Option<u32>,
+    pub auth_token: Option<String>,
+    pub tenants: Vec<(String, String)>,
+    pub token_scopes: Vec<(String, Vec<Scope>)>,
+    pub api_keys: Vec<ApiKey>,
+    // Where the API keys are reloaded from
+    pub config_path: Option<PathBuf>,
+    pub audit_log: Option<PathBuf>,
+    pub audit_strict: bool,
+    pub retention_days: Option<u32>,
+    pub retention_interval: Duration,
+    pub retention_archive_dir: Option<PathBuf>,
+    pub webhook_url: Option<String>,
+    pub webhook_timeout: Duration,
+    pub log_file: Option<PathBuf>,
+    pub log_rotation: LogRotation,
+    pub log_max_files: Option<usize>,
+    #[cfg(feature = "sqlite")]
+    pub index_path: Option<PathBuf>,
+}
+
+impl AppConfig {
+    // Current time in the configured timezone, day folders and archives are named after it
+    pub fn now(&self) -> DateTime<Tz> {
+        Utc::now().with_timezone(&self.timezone)
+    }
+
+    // Responses are only ever meant for the token that fetched them, so shared caches keep out
+    pub fn listing_cache_control(&self) -> String {
+        format!("private, max-age={}", self.listing_max_age.as_secs())
+    }
+
+    // Archives carry an ETag, once stale they're revalidated rather than fetched again
+    pub fn download_cache_control(&self) -> String {
+        format!("private, max-age={}, must-revalidate", self.download_max_age.as_secs())
+    }
+
+    // Catches settings that parse but make no sense, alone or together, before they surface as
+    // failing requests. Returns every problem at once so they can be fixed in one go. Tokens are
+    // never part of the messages.
+    pub fn validate(&self) -> Result<(), Vec<String>> {
+        let mut errors = Vec::new();
+
+        let positive = [
+            ("--max-upload-size", self.max_upload_size as u64),
+            ("--max-files-per-request", self.max_files_per_request as u64),
+            ("--max-concurrent-requests", self.max_concurrent_requests as u64),
+            ("--max-concurrent-uploads", self.max_concurrent_uploads as u64),
+            ("--max-resumable-upload-size", self.max_resumable_upload_size),
+            ("--request-timeout", self.request_timeout.as_secs()),
+            ("--resumable-upload-ttl", self.resumable_upload_ttl.as_secs()),
+            ("--retention-interval", self.retention_interval.as_secs()),
+            ("--webhook-timeout", self.webhook_timeout.as_secs()),
+        ];
+        for (name, value) in positive {
+            if value == 0 {
+                errors.push(format!("{} must be greater than 0", name));
+            }
+        }
+
+        if self.retention_days == Some(0) {
+            errors.push("--retention-days must be at least 1, 0 would delete every upload by the next day".to_string());
+        }
+        if self.upload_rate_limit == Some(0) {
+            errors.push("--upload-rate-limit of 0 would refuse every upload, leave it unset for no limit".to_string());
+        }
+        if self.compression_level.is_some_and(|level| level > 9) {
+            errors.push("--compression-level must be between 0 and 9".to_string());
+        }
+        if self.log_max_files == Some(0) {
+            errors.push("--log-max-files must be at least 1, the current file counts too".to_string());
+        }
+        if self.log_file.as_ref().is_some_and(|path| path.file_name().is_none()) {
+            errors.push("--log-file must name a file, not a directory".to_string());
+        }
+        if self.upload_field.is_empty() {
+            errors.push("--upload-field must not be empty".to_string());
+        }
+
+        if let Some(max_total_bytes) = self.max_total_bytes
+            && max_total_bytes <= self.max_upload_size as u64
+        {
+            errors.push(format!(
+                "--max-total-bytes ({}) must be larger than --max-upload-size ({}), or not even one upload fits",
+                max_total_bytes,
+                self.max_upload_size
+            ));
+        }
+        if let Some(archive_dir) = &self.retention_archive_dir {
+            let absolute = |path: &Path| std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+            if absolute(archive_dir).starts_with(absolute(&self.data_dir)) {
+                errors.push("--retention-archive-dir has to be outside of the data dir".to_string());
+            }
+            if self.retention_days.is_none() {
+                errors.push("--retention-archive-dir does nothing without --retention-days".to_string());
+            }
+        }
+        if self.dedup && !matches!(self.storage, StorageConfig::Local) {
+            errors.push("--dedup only works with local storage".to_string());
+        }
+
+        // A token that authenticates as two different things depends on the order of the checks
+        let mut tokens: Vec<&str> = self.auth_token.iter().map(String::as_str).collect();
+        tokens.extend(self.tenants.iter().map(|(token, _)| token.as_str()));
+        tokens.extend(self.api_keys.iter().map(|key| key.key.as_str()));
+        let mut seen = std::collections::HashSet::new();
+        if tokens.iter().any(|token| !seen.insert(*token)) {
+            errors.push("The same token is configured more than once across the auth token, tenants and API keys".to_string());
+        }
+
+        for (position, (token, _)) in self.token_scopes.iter().enumerate() {
+            let known = self.auth_token.as_deref() == Some(token.as_str())
+                || self.tenants.iter().any(|(tenant_token, _)| tenant_token == token);
+            if !known {
+                errors.push(format!(
+                    "--token-scope entry {} names neither the auth token nor a tenant token",
+                    position + 1
+                ));
+            }
+        }
+
+        match errors.is_empty() {
+            true => Ok(()),
+            false => Err(errors),
+        }
+    }
+}
+
+impl From<Args> for AppConfig {
+    fn from(args: Args) -> Self {
+        // clap already insists on the S3 settings when S3 storage is selected
+        let storage = match args.storage {
+            StorageKind::Local => StorageConfig::Local,
+            StorageKind::S3 => StorageConfig::S3 {
+                endpoint: args.s3_endpoint.unwrap_or_default(),
+                bucket: args.s3_bucket.unwrap_or_default(),
+                region: args.s3_region,
+                access_key: args.s3_access_key.unwrap_or_default(),
+                secret_key: args.s3_secret_key.unwrap_or_default(),
+            },
+        };
+
+        Self {
+            bind: args.bind,
+            trusted_proxies: args.trusted_proxies,
+            data_dir: args.data_dir,
+            timezone: args.timezone,
+            storage,
+            dedup: args.dedup,
+            max_upload_size: args.max_upload_size,
+            max_files_per_request: args.max_files_per_request,
+            max_resumable_upload_size: args.max_resumable_upload_size,
+            resumable_upload_ttl: Duration::from_secs(args.resumable_upload_ttl),
+            upload_field: args.upload_field,
+            max_concurrent_requests: args.max_concurrent_requests,
+            max_concurrent_uploads: args.max_concurrent_uploads,
+            request_timeout: Duration::from_secs(args.request_timeout),
+            idempotency_ttl: Duration::from_secs(args.idempotency_ttl),
+            max_total_bytes: args.max_total_bytes,
+            min_free_bytes: args.min_free_bytes,
+            compression_method: CompressionMethod::Deflated,
+            compression_level: args.compression_level,
+            compression_threads: match args.compression_threads {
+                0 => std::thread::available_parallelism().map_or(1, |n| n.get()),
+                threads => threads,
+            },
+            listing_max_age: Duration::from_secs(args.listing_max_age),
+            download_max_age: Duration::from_secs(args.download_max_age),
+            archive_file_mode: args.archive_file_mode,
+            upload_rate_limit: args.upload_rate_limit,
+            auth_token: args.auth_token,
+            tenants: args.tenants,
+            token_scopes: args.token_scopes,
+            api_keys: Vec::new(),
+            config_path: args.config,
+            audit_log: args.audit_log,
+            audit_strict: args.audit_strict,
+            retention_days: args.retention_days,
+            retention_interval: Duration::from_secs(args.retention_interval),
+            retention_archive_dir: args.retention_archive_dir,
+            webhook_url: args.webhook_url,
+            webhook_timeout: Duration::from_secs(args.webhook_timeout),
+            log_file: args.log_file,
+            log_rotation: args.log_rotation,
+            log_max_files: args.log_max_files,
+            #[cfg(feature = "sqlite")]
+            index_path: args.index_path,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::tests::config;
+
+    fn errors(args: &[&str]) -> Vec<String> {
+        config(Path::new("/srv/eotwsink"), args).validate().err().unwrap_or_default()
+    }
+
+    // Passes when exactly one problem is reported and it mentions `expected`
+    fn assert_rejected(args: &[&str], expected: &str) {
+        let errors = errors(args);
+        assert_eq!(errors.len(), 1, "{:?} for {:?}", errors, args);
+        assert!(errors[0].contains(expected), "{:?} for {:?}", errors, args);
+    }
+
+    #[test]
+    fn defaults_are_valid() {
+        assert_eq!(errors(&[]), Vec::<String>::new());
+    }
+
+    #[test]
+    fn limits_and_timeouts_must_be_positive() {
+        for flag in [
+            "--max-upload-size",
+            "--max-files-per-request",
+            "--max-concurrent-requests",
+            "--max-concurrent-uploads",
+            "--max-resumable-upload-size",
+            "--request-timeout",
+            "--resumable-upload-ttl",
+            "--retention-interval",
+            "--webhook-timeout",
+        ] {
+            assert_rejected(&[flag, "0"], &format!("{} must be greater than 0", flag));
+        }
+    }
+
+    #[test]
+    fn retention_days_must_keep_something() {
+        assert_rejected(&["--retention-days", "0"], "--retention-days");
+        assert_eq!(errors(&["--retention-days", "1"]), Vec::<String>::new());
+    }
+
+    #[test]
+    fn rate_limit_must_let_uploads_through() {
+        assert_rejected(&["--upload-rate-limit", "0"], "--upload-rate-limit");
+    }
+
+    #[test]
+    fn compression_level_must_be_in_range() {
+        // clap already refuses these, a config file could still get one through
+        let mut config = config(Path::new("/srv/eotwsink"), &[]);
+        config.compression_level = Some(10);
+        assert_eq!(config.validate().unwrap_err().len(), 1);
+        config.compression_level = Some(9);
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn log_files_must_be_kept_and_named() {
+        assert_rejected(&["--log-max-files", "0"], "--log-max-files");
+        assert_rejected(&["--log-file", "/var/log/.."], "--log-file");
+    }
+
+    #[test]
+    fn upload_field_must_not_be_empty() {
+        assert_rejected(&["--upload-field", ""], "--upload-field");
+    }
+
+    #[test]
+    fn total_bytes_must_fit_an_upload() {
+        assert_rejected(&["--max-upload-size", "100", "--max-total-bytes", "100"], "--max-total-bytes (100)");
+        assert_eq!(errors(&["--max-upload-size", "100", "--max-total-bytes", "101"]), Vec::<String>::new());
+    }
+
+    #[test]
+    fn archive_dir_must_be_outside_the_data_dir() {
+        assert_rejected(
+            &["--retention-days", "7", "--retention-archive-dir", "/srv/eotwsink/archive"],
+            "outside of the data dir",
+        );
+        assert_eq!(
+            errors(&["--retention-days", "7", "--retention-archive-dir", "/srv/archive"]),
+            Vec::<String>::new()
+        );
+    }
+
+    #[test]
+    fn archive_dir_needs_retention() {
+        assert_rejected(&["--retention-archive-dir", "/srv/archive"], "without --retention-days");
+    }
+
+    #[test]
+    fn dedup_needs_local_storage() {
+        let s3 = [
+            "--storage", "s3", "--s3-endpoint", "http://localhost:9000", "--s3-bucket", "logs",
+            "--s3-access-key", "access", "--s3-secret-key", "secret",
+        ];
+        assert_eq!(errors(&s3), Vec::<String>::new());
+        assert_rejected(&[&s3[..], &["--dedup"]].concat(), "--dedup");
+    }
+
+    #[test]
+    fn tokens_must_be_unique() {
+        assert_rejected(&["--auth-token", "shared", "--tenant", "shared=acme"], "more than once");
+        assert_rejected(&["--tenant", "shared=acme", "--tenant", "shared=globex"], "more than once");
+    }
+
+    #[test]
+    fn duplicate_tokens_are_never_echoed() {
+        let errors = errors(&["--auth-token", "hunter2", "--tenant", "hunter2=acme"]);
+        assert!(errors.iter().all(|error| !error.contains("hunter2")), "{:?}", errors);
+    }
+
+    #[test]
+    fn token_scopes_must_name_a_known_token() {
+        assert_rejected(&["--auth-token", "root", "--token-scope", "other=download"], "--token-scope entry 1");
+        assert_eq!(
+            errors(&["--auth-token", "root", "--tenant", "acme-token=acme", "--token-scope", "acme-token=download"]),
+            Vec::<String>::new()
+        );
+    }
+
+    #[test]
+    fn every_problem_is_reported_at_once() {
+        assert_eq!(errors(&["--max-upload-size", "0", "--upload-field", "", "--retention-days", "0"]).len(), 3);
+    }
+}
@@ -0,0 +1,103 @@
+use std::{
+    io::{self, Write},
+    sync::Arc,
+};
+
+use axum::{
+    body::{Body, Bytes},
+    extract::{Request, State},
+    http::header,
+    middleware::Next,
+    response::Response,
+};
+use flate2::write::GzDecoder;
+use futures_util::StreamExt;
+
+use crate::{ApiError, AppState};
+
+// Collects decompressed output, but never more than one byte past the limit. That byte is enough
+// for the body limit to reject the upload, anything beyond is dropped instead of buffered.
+struct Capped {
+    buffer: Vec<u8>,
+    written: usize,
+    limit: usize,
+}
+
+impl Capped {
+    fn overflowed(&self) -> bool {
+        self.written > self.limit
+    }
+}
+
+impl Write for Capped {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        let room = (self.limit + 1).saturating_sub(self.written);
+        let kept = data.len().min(room);
+        self.buffer.extend_from_slice(&data[..kept]);
+        self.written += kept;
+
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Decompresses gzip encoded upload bodies on the fly, so handlers and the body limit only ever
+// see the original bytes. The compressed input is held to the same limit.
+pub async fn gunzip_uploads(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Result<Response, ApiError> {
+    let encoding = request.headers()
+        .get(header::CONTENT_ENCODING)
+        .map(|v| v.to_str().unwrap_or_default().trim().to_ascii_lowercase());
+
+    match encoding.as_deref() {
+        None | Some("identity") => return Ok(next.run(request).await),
+        Some("gzip" | "x-gzip") => {}
+        Some(other) => {
+            return Err(ApiError::UnsupportedMediaType(format!("unsupported content encoding {}", other)));
+        }
+    }
+
+    let limit = state.config.max_upload_size;
+    let (mut parts, body) = request.into_parts();
+    parts.headers.remove(header::CONTENT_ENCODING);
+    parts.headers.remove(header::CONTENT_LENGTH);
+
+    let decoder = GzDecoder::new(Capped { buffer: Vec::new(), written: 0, limit });
+    let initial = (body.into_data_stream(), decoder, 0);
+    let chunks = futures_util::stream::unfold(Some(initial), move |state| async move {
+        let (mut body, mut decoder, mut read) = state?;
+        if decoder.get_ref().overflowed() {
+            return Some((Err(io::Error::other("decompressed body exceeds the upload limit")), None));
+        }
+
+        let done = match body.next().await {
+            Some(Ok(chunk)) => {
+                read += chunk.len();
+                if read > limit {
+                    Err(io::Error::other("compressed body exceeds the upload limit"))
+                } else {
+                    decoder.write_all(&chunk).map(|_| false)
+                }
+            }
+            Some(Err(e)) => Err(io::Error::other(e)),
+            None => decoder.try_finish().map(|_| true),
+        };
+
+        match done {
+            Ok(done) => {
+                let output = Bytes::from(std::mem::take(&mut decoder.get_mut().buffer));
+                Some((Ok(output), (!done).then_some((body, decoder, read))))
+            }
+            Err(e) => Some((Err(e), None)),
+        }
+    });
+
+    let request = Request::from_parts(parts, Body::from_stream(chunks));
+    Ok(next.run(request).await)
+}
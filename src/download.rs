@@ -0,0 +1,615 @@
+use std::{collections::HashMap, path::Path, sync::Arc, time::SystemTime};
+
+use axum::{
+    Json,
+    body::Body,
+    extract::{Path as UrlPath, State},
+    http::{HeaderMap, Method, StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use axum_extra::extract::Query;
+use chrono::{DateTime, Offset, Utc};
+use serde::Deserialize;
+use serde_json::json;
+use tokio_util::io::{ReaderStream, SyncIoBridge};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{ApiError, AppState, archive::{self, ArchiveFormat, ArchiveOptions}, audit, client::ClientIp, config::StorageConfig, files::{self, DayRange}, metrics::Measured, retention, storage::{self, StorageBackend, StoredFile}, tenant::Tenant};
+
+// Number of files in the archive, so an empty archive can be told apart without unpacking it
+const FILE_COUNT: &str = "x-file-count";
+// Alternative to the password query parameter that stays out of URLs and access logs
+const PASSWORD_HEADER: &str = "x-archive-password";
+
+// Weak comparison against an If-None-Match header, which may hold a list of tags or `*`
+fn etag_matches(headers: &HeaderMap, etag: &str) -> bool {
+    let Some(value) = headers.get(header::IF_NONE_MATCH).and_then(|v| v.to_str().ok()) else {
+        return false;
+    };
+
+    let strip = |tag: &str| tag.trim().trim_start_matches("W/").to_string();
+    value.split(',').any(|tag| tag.trim() == "*" || strip(tag) == strip(etag))
+}
+
+// HTTP dates only carry whole seconds, so compare at that granularity
+fn not_modified_since(headers: &HeaderMap, last_modified: SystemTime) -> bool {
+    let Some(since) = headers.get(header::IF_MODIFIED_SINCE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| DateTime::parse_from_rfc2822(v).ok())
+    else {
+        return false;
+    };
+
+    DateTime::<Utc>::from(last_modified).timestamp() <= since.timestamp()
+}
+
+fn http_date(time: SystemTime) -> String {
+    DateTime::<Utc>::from(time).format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
+// Streams an archive of `entries`, paired with their names inside the archive, as an attachment
+// named `basename` plus the format's extension. The archive is built on a blocking thread and
+// piped into the body as it's written.
+// For HEAD requests only the headers are produced, the exact size isn't known without
+// building the archive. Only archives actually handed out are recorded in the audit log.
+#[allow(clippy::too_many_arguments)]
+async fn archive_response(
+    state: &AppState,
+    tenant: &Tenant,
+    method: &Method,
+    headers: &HeaderMap,
+    client: &ClientIp,
+    entries: Vec<(StoredFile, String)>,
+    options: ArchiveOptions,
+    basename: &str,
+) -> Result<Response, ApiError> {
+    let content_type = options.format.content_type();
+    let filename = format!("{}.{}", basename, options.format.extension());
+    let fingerprint = archive::fingerprint(&entries, &options);
+
+    // If-None-Match takes precedence over If-Modified-Since when both are sent
+    let not_modified = if headers.contains_key(header::IF_NONE_MATCH) {
+        etag_matches(headers, &fingerprint.etag)
+    } else {
+        fingerprint.last_modified.is_some_and(|time| not_modified_since(headers, time))
+    };
+
+    // The format may have been negotiated, caches must not hand a zip to a client asking for a tar
+    let mut builder = Response::builder()
+        .header(FILE_COUNT, entries.len())
+        .header(header::VARY, "accept")
+        .header(header::ETAG, fingerprint.etag)
+        .header(header::CACHE_CONTROL, state.config.download_cache_control())
+        .header(header::CONTENT_TYPE, content_type)
+        .header(
+            header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{}\"", filename)
+        );
+    if let Some(time) = fingerprint.last_modified {
+        builder = builder.header(header::LAST_MODIFIED, http_date(time));
+    }
+
+    if not_modified {
+        return builder
+            .status(StatusCode::NOT_MODIFIED)
+            .body(Body::empty())
+            .map_err(|e| ApiError::InternalError(format!("Failed to build response: {}", e)));
+    }
+
+    if method == Method::HEAD {
+        return builder
+            .status(StatusCode::OK)
+            .body(Body::empty())
+            .map_err(|e| ApiError::InternalError(format!("Failed to build response: {}", e)));
+    }
+
+    let paths = entries.iter().map(|(file, _)| file.key.clone()).collect();
+    audit::record(state, "download", client, tenant, paths)?;
+
+    let (reader, writer) = tokio::io::duplex(64 * 1024);
+    let writer = SyncIoBridge::new(writer);
+    let storage = state.storage_for(tenant);
+    tokio::task::spawn_blocking(move || {
+        if let Err(e) = archive::write_archive(writer, storage.as_ref(), &entries, &options) {
+            tracing::error!(error = %e, "Failed to stream archive");
+        }
+    });
+
+    state.metrics.downloads.inc();
+
+    builder
+        .status(StatusCode::OK)
+        .body(Body::from_stream(Measured::new(ReaderStream::new(reader), state.metrics.download_size.clone())))
+        .map_err(|e| ApiError::InternalError(format!("Failed to build response: {}", e)))
+}
+
+// Time for archive names in the configured timezone, with a Z or offset suffix so names from
+// different servers can't be confused
+fn archive_timestamp(state: &AppState) -> String {
+    let now = state.config.now();
+    if now.offset().fix().local_minus_utc() == 0 {
+        now.format("%Y%m%d_%H%M%SZ").to_string()
+    } else {
+        now.format("%Y%m%d_%H%M%S%z").to_string()
+    }
+}
+
+// The server itself only speaks plain HTTP, so a request was only encrypted if a proxy in front
+// terminated TLS and says so
+fn forwarded_https(headers: &HeaderMap) -> bool {
+    headers.get("x-forwarded-proto")
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|proto| proto.eq_ignore_ascii_case("https"))
+}
+
+// Streams the tarball of an archived day as is, the download filters don't apply to it. The
+// tarball never changes once packed, so its modification time is all revalidation needs.
+async fn archived_response(
+    state: &AppState,
+    tenant: &Tenant,
+    method: &Method,
+    headers: &HeaderMap,
+    client: &ClientIp,
+    date: &str,
+    path: &Path,
+) -> Result<Response, ApiError> {
+    let file = tokio::fs::File::open(path).await
+        .map_err(|e| ApiError::InternalError(format!("Failed to open archived day: {}", e)))?;
+    let metadata = file.metadata().await
+        .map_err(|e| ApiError::InternalError(format!("Failed to open archived day: {}", e)))?;
+
+    let mut builder = Response::builder()
+        .header(header::CONTENT_TYPE, ArchiveFormat::TarGz.content_type())
+        .header(header::CACHE_CONTROL, state.config.download_cache_control())
+        .header(
+            header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"logs_{}.{}\"", date, ArchiveFormat::TarGz.extension())
+        );
+    if let Ok(modified) = metadata.modified() {
+        builder = builder.header(header::LAST_MODIFIED, http_date(modified));
+
+        if not_modified_since(headers, modified) {
+            return builder
+                .status(StatusCode::NOT_MODIFIED)
+                .body(Body::empty())
+                .map_err(|e| ApiError::InternalError(format!("Failed to build response: {}", e)));
+        }
+    }
+    let builder = builder
+        .status(StatusCode::OK)
+        .header(header::CONTENT_LENGTH, metadata.len());
+
+    if method == Method::HEAD {
+        return builder
+            .body(Body::empty())
+            .map_err(|e| ApiError::InternalError(format!("Failed to build response: {}", e)));
+    }
+
+    audit::record(state, "download", client, tenant, vec![format!("{}/", date)])?;
+    state.metrics.downloads.inc();
+
+    builder
+        .body(Body::from_stream(Measured::new(ReaderStream::new(file), state.metrics.download_size.clone())))
+        .map_err(|e| ApiError::InternalError(format!("Failed to build response: {}", e)))
+}
+
+// Every stored file that passes the options' filters. The data dir is recreated if it went
+// missing, so a server without logs yields an empty archive. Only a data dir that can't be
+// created ends up as a 404.
+async fn stored_entries(state: &AppState, tenant: &Tenant, options: &ArchiveOptions) -> Result<Vec<StoredFile>, ApiError> {
+    if matches!(state.config.storage, StorageConfig::Local)
+        && let Err(e) = tokio::fs::create_dir_all(&state.config.data_dir).await
+    {
+        tracing::error!(path = %state.config.data_dir.display(), error = %e, "Failed to create data directory");
+    }
+
+    let stored = storage::blocking(&state.storage_for(tenant), |storage| storage.list()).await
+        .map_err(|e| storage::api_error("Failed to list files", e))?;
+
+    Ok(stored.into_iter().filter(|file| options.includes(file)).collect())
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct DownloadQuery {
+    /// First day to include, as YYYY-MM-DD
+    from: Option<String>,
+    /// Last day to include, as YYYY-MM-DD
+    to: Option<String>,
+    /// zip, tar or targz, overriding the Accept header
+    format: Option<String>,
+    /// deflate, stored or zstd, zip only. zstd needs a server built with the zstd feature and
+    /// is deflated otherwise. Info-ZIP unzip can't open zstd entries, bsdtar can
+    compression: Option<String>,
+    /// Only files uploaded after this time, as unix seconds or RFC 3339
+    since: Option<String>,
+    /// Fixed timestamps, permissions and entry order, so equal contents give byte-identical
+    /// archives. Can't be combined with a password
+    #[serde(default)]
+    deterministic: bool,
+    /// Only paths matching one of these globs, e.g. *.log. May be repeated
+    #[serde(default)]
+    include: Vec<String>,
+    /// Leave out paths matching one of these globs. May be repeated
+    #[serde(default)]
+    exclude: Vec<String>,
+    /// Encrypt the zip with AES-256 under this password, also accepted as X-Archive-Password
+    password: Option<String>,
+}
+
+impl DownloadQuery {
+    fn options(&self, state: &AppState, headers: &HeaderMap, range: DayRange) -> Result<ArchiveOptions, ApiError> {
+        let format = match &self.format {
+            Some(value) => ArchiveFormat::parse(value)?,
+            None => ArchiveFormat::negotiate(headers)?,
+        };
+
+        let compression_method = match &self.compression {
+            Some(value) => archive::parse_compression(value)?,
+            None => state.config.compression_method,
+        };
+
+        let password = self.password.clone().or_else(|| {
+            headers.get(PASSWORD_HEADER).and_then(|v| v.to_str().ok()).map(str::to_string)
+        });
+        match &password {
+            Some(password) if password.is_empty() => {
+                return Err(ApiError::BadRequest("Empty archive password".to_string()));
+            }
+            Some(_) if format != ArchiveFormat::Zip => {
+                return Err(ApiError::BadRequest("Passwords are only supported for zip archives".to_string()));
+            }
+            // AES salts are random, no two encrypted archives are the same
+            Some(_) if self.deterministic => {
+                return Err(ApiError::BadRequest("Encrypted archives can't be deterministic".to_string()));
+            }
+            Some(_) if !forwarded_https(headers) => {
+                tracing::warn!("Archive password was sent over plain HTTP");
+            }
+            _ => {}
+        }
+
+        Ok(ArchiveOptions {
+            format,
+            range,
+            since: self.since.as_deref().map(files::parse_since).transpose()?,
+            compression_method,
+            compression_level: state.config.compression_level,
+            compression_threads: state.config.compression_threads,
+            deterministic: self.deterministic,
+            timezone: state.config.timezone,
+            file_mode: state.config.archive_file_mode,
+            include: archive::parse_globs(&self.include)?,
+            exclude: archive::parse_globs(&self.exclude)?,
+            password,
+        })
+    }
+}
+
+#[utoipa::path(
+    method(get, head),
+    path = "/download",
+    description = "Archive of all stored files, optionally narrowed to a range of days",
+    params(DownloadQuery),
+    responses(
+        (status = 200, description = "Zip archive, or a plain or gzipped tarball", content_type = "application/zip", body = Vec<u8>),
+        (status = 304, description = "Unchanged since the given ETag or date"),
+        (status = 400, description = "Invalid query", body = crate::openapi::ErrorBody),
+        (status = 401, description = "Missing or invalid bearer token", body = crate::openapi::ErrorBody),
+        (status = 406, description = "Accept rules out zip, tar and gzip alike", body = crate::openapi::ErrorBody),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn download_log(
+    State(state): State<Arc<AppState>>,
+    client: ClientIp,
+    tenant: Tenant,
+    method: Method,
+    headers: HeaderMap,
+    Query(query): Query<DownloadQuery>,
+) -> Result<impl IntoResponse, ApiError> {
+    let range = DayRange::parse(query.from.as_deref(), query.to.as_deref())?;
+    let options = query.options(&state, &headers, range)?;
+    let entries: Vec<_> = stored_entries(&state, &tenant, &options).await?
+        .into_iter()
+        .map(|file| {
+            let name = file.key.clone();
+            (file, name)
+        })
+        .collect();
+    let timestamp = archive_timestamp(&state);
+
+    archive_response(&state, &tenant, &method, &headers, &client, entries, options, &format!("logs_{}", timestamp)).await
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ManifestQuery {
+    /// First day to include, as YYYY-MM-DD
+    from: Option<String>,
+    /// Last day to include, as YYYY-MM-DD
+    to: Option<String>,
+}
+
+// Lists what `download_log` would put into the archive without building it
+#[utoipa::path(
+    get,
+    path = "/download/manifest",
+    params(ManifestQuery),
+    responses(
+        (status = 200, body = crate::openapi::Manifest),
+        (status = 400, description = "Invalid query", body = crate::openapi::ErrorBody),
+        (status = 401, description = "Missing or invalid bearer token", body = crate::openapi::ErrorBody),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn download_manifest(
+    State(state): State<Arc<AppState>>,
+    tenant: Tenant,
+    Query(query): Query<ManifestQuery>,
+) -> Result<impl IntoResponse, ApiError> {
+    let range = DayRange::parse(query.from.as_deref(), query.to.as_deref())?;
+
+    let stored = storage::blocking(&state.storage_for(&tenant), |storage| storage.list()).await
+        .map_err(|e| storage::api_error("Failed to list files", e))?;
+    let entries: Vec<_> = stored.iter().filter(|file| range.contains(&file.key)).collect();
+    let total_bytes: u64 = entries.iter().map(|file| file.size).sum();
+
+    Ok(Json(json!({
+        "files": entries.len(),
+        "total_bytes": total_bytes,
+        "entries": entries.iter().map(|file| file.describe()).collect::<Vec<_>>()
+    })))
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct SelectionRequest {
+    /// Paths as listed by /files
+    paths: Vec<String>,
+}
+
+// Archives an explicit list of files. All of them have to exist, a partial archive would be
+// easy to mistake for a complete one.
+#[utoipa::path(
+    post,
+    path = "/download",
+    description = "Archive of the listed files",
+    params(DownloadQuery),
+    request_body = SelectionRequest,
+    responses(
+        (status = 200, description = "Zip archive, or a plain or gzipped tarball", content_type = "application/zip", body = Vec<u8>),
+        (status = 304, description = "Unchanged since the given ETag or date"),
+        (status = 400, description = "No paths given or some files don't exist", body = crate::openapi::ErrorBody),
+        (status = 401, description = "Missing or invalid bearer token", body = crate::openapi::ErrorBody),
+        (status = 406, description = "Accept rules out zip, tar and gzip alike", body = crate::openapi::ErrorBody),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn download_selection(
+    State(state): State<Arc<AppState>>,
+    client: ClientIp,
+    tenant: Tenant,
+    headers: HeaderMap,
+    Query(query): Query<DownloadQuery>,
+    Json(selection): Json<SelectionRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    let options = query.options(&state, &headers, DayRange::default())?;
+
+    if selection.paths.is_empty() {
+        return Err(ApiError::BadRequest("No paths given".to_string()));
+    }
+
+    let mut stored: HashMap<String, StoredFile> = storage::blocking(&state.storage_for(&tenant), |storage| storage.list()).await
+        .map_err(|e| storage::api_error("Failed to list files", e))?
+        .into_iter()
+        .map(|file| (file.key.clone(), file))
+        .collect();
+
+    // Only listed keys can match, so nothing outside of the storage can be selected
+    let mut selected = Vec::new();
+    let mut missing = Vec::new();
+
+    for relative in &selection.paths {
+        let name = Path::new(relative)
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        if selected.iter().any(|(_, selected)| *selected == name) {
+            continue;
+        }
+
+        match stored.remove(&name) {
+            Some(file) => selected.push((file, name)),
+            None => missing.push(relative.clone()),
+        }
+    }
+
+    if !missing.is_empty() {
+        return Err(ApiError::BadRequest(format!("Files not found: {}", missing.join(", "))));
+    }
+
+    // The order paths were listed in mustn't change the archive either
+    if options.deterministic {
+        selected.sort_by(|a, b| a.1.cmp(&b.1));
+    }
+
+    let timestamp = archive_timestamp(&state);
+
+    archive_response(&state, &tenant, &Method::POST, &headers, &client, selected, options, &format!("logs_{}", timestamp)).await
+}
+
+#[utoipa::path(
+    get,
+    path = "/download/{date}",
+    params(("date" = String, Path, description = "Day as YYYY-MM-DD"), DownloadQuery),
+    responses(
+        (status = 200, description = "Zip archive, or a plain or gzipped tarball, named like logs_2024-01-01_42files.zip. Archived days are served as the logs_2024-01-01.tar.gz they were packed into", content_type = "application/zip", body = Vec<u8>),
+        (status = 304, description = "Unchanged since the given ETag or date"),
+        (status = 400, description = "Invalid date or query", body = crate::openapi::ErrorBody),
+        (status = 401, description = "Missing or invalid bearer token", body = crate::openapi::ErrorBody),
+        (status = 406, description = "Accept rules out zip, tar and gzip alike", body = crate::openapi::ErrorBody),
+        (status = 404, description = "No files stored that day", body = crate::openapi::ErrorBody),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn download_day(
+    State(state): State<Arc<AppState>>,
+    client: ClientIp,
+    tenant: Tenant,
+    UrlPath(date): UrlPath<String>,
+    method: Method,
+    headers: HeaderMap,
+    Query(query): Query<DownloadQuery>,
+) -> Result<impl IntoResponse, ApiError> {
+    let day = files::parse_day_param(&date)?;
+    let options = query.options(&state, &headers, DayRange { from: Some(day), to: Some(day) })?;
+
+    // Entries are named relative to the day folder
+    let prefix = format!("{}/", date);
+    let entries: Vec<_> = stored_entries(&state, &tenant, &options).await?
+        .into_iter()
+        .filter_map(|file| {
+            let name = file.key.strip_prefix(&prefix)?.to_string();
+            Some((file, name))
+        })
+        .collect();
+    if entries.is_empty() {
+        // A day the retention sweep packed away is served as the tarball it was packed into
+        if let Some(archive_dir) = state.archive_dir_for(&tenant)
+            && let Some(path) = retention::archived_day(&archive_dir, &date)
+        {
+            if options.password.is_some() {
+                return Err(ApiError::BadRequest("Archived days can't be encrypted".to_string()));
+            }
+            return archived_response(&state, &tenant, &method, &headers, &client, &date, &path).await;
+        }
+        return Err(ApiError::NotFound);
+    }
+    // The count in the name makes a truncated pull stand out among saved archives
+    let basename = format!("logs_{}_{}files", date, entries.len());
+    archive_response(&state, &tenant, &method, &headers, &client, entries, options, &basename).await
+}
+
+#[utoipa::path(
+    delete,
+    path = "/download/{date}",
+    params(("date" = String, Path, description = "Day as YYYY-MM-DD")),
+    responses(
+        (status = 200, body = crate::openapi::DeletedDay),
+        (status = 400, description = "Invalid date", body = crate::openapi::ErrorBody),
+        (status = 401, description = "Missing or invalid bearer token", body = crate::openapi::ErrorBody),
+        (status = 404, description = "No folder for that day", body = crate::openapi::ErrorBody),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn delete_day(
+    State(state): State<Arc<AppState>>,
+    client: ClientIp,
+    tenant: Tenant,
+    UrlPath(date): UrlPath<String>,
+) -> Result<impl IntoResponse, ApiError> {
+    files::parse_day_param(&date)?;
+
+    let dir = date.clone();
+    let (deleted_files, deleted_bytes) = storage::blocking(&state.storage_for(&tenant), move |storage| storage.delete_dir(&dir)).await
+        .map_err(|e| storage::api_error("Failed to delete day", e))?;
+    state.release_used_bytes(deleted_bytes);
+    tracing::info!(date = %tenant.scope(&date), deleted_files, deleted_bytes, %client, "Day deleted");
+    // Returned only after the index is cleaned up, the files are gone either way
+    let audited = audit::record(&state, "delete", &client, &tenant, vec![date.clone()]);
+
+    #[cfg(feature = "sqlite")]
+    if let Some(index) = &state.index
+        && let Err(e) = index.remove_dir(&tenant.scope(&date))
+    {
+        tracing::error!(%date, error = %e, "Failed to remove day from index");
+    }
+    audited?;
+
+    Ok(Json(json!({
+        "status": "success",
+        "date": date,
+        "deleted_files": deleted_files
+    })))
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct DeleteRangeQuery {
+    /// First day to delete, as YYYY-MM-DD
+    from: Option<String>,
+    /// Last day to delete, as YYYY-MM-DD
+    to: Option<String>,
+}
+
+// Removes the days holding files that fall into `range`. Returns their dates along with the
+// number of files and bytes they held.
+fn remove_days(storage: &dyn StorageBackend, range: DayRange) -> std::io::Result<(Vec<String>, usize, u64)> {
+    let mut removed = (Vec::new(), 0, 0);
+    for date in files::stored_days(storage)?.into_keys() {
+        if !files::parse_day(&date).is_some_and(|day| range.contains_day(day)) {
+            continue;
+        }
+
+        let (count, bytes) = storage.delete_dir(&date)?;
+        removed.0.push(date);
+        removed.1 += count;
+        removed.2 += bytes;
+    }
+
+    Ok(removed)
+}
+
+// Wipes every day folder in an inclusive range at once, meant for cleaning up after incidents.
+// One bound at least is required so a forgotten parameter can't delete everything.
+#[utoipa::path(
+    delete,
+    path = "/download",
+    params(DeleteRangeQuery),
+    responses(
+        (status = 200, body = crate::openapi::DeletedRange),
+        (status = 400, description = "Neither from nor to given, or an invalid date", body = crate::openapi::ErrorBody),
+        (status = 401, description = "Missing or invalid bearer token", body = crate::openapi::ErrorBody),
+        (status = 403, description = "The token lacks the admin scope", body = crate::openapi::ErrorBody),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn delete_range(
+    State(state): State<Arc<AppState>>,
+    client: ClientIp,
+    tenant: Tenant,
+    Query(query): Query<DeleteRangeQuery>,
+) -> Result<impl IntoResponse, ApiError> {
+    let range = DayRange::parse(query.from.as_deref(), query.to.as_deref())?;
+    if range.is_unbounded() {
+        return Err(ApiError::BadRequest("from or to is required to delete a range of days".to_string()));
+    }
+
+    let (dates, deleted_files, deleted_bytes) = storage::blocking(&state.storage_for(&tenant), move |storage| remove_days(storage, range)).await
+        .map_err(|e| storage::api_error("Failed to delete days", e))?;
+    state.release_used_bytes(deleted_bytes);
+    tracing::info!(from = ?query.from, to = ?query.to, folders = dates.len(), deleted_files, deleted_bytes, %client, "Days deleted");
+    // Returned only after the index is cleaned up, the files are gone either way
+    let audited = match dates.is_empty() {
+        true => Ok(()),
+        false => audit::record(&state, "delete", &client, &tenant, dates.clone()),
+    };
+
+    #[cfg(feature = "sqlite")]
+    if let Some(index) = &state.index {
+        for date in &dates {
+            if let Err(e) = index.remove_dir(&tenant.scope(date)) {
+                tracing::error!(%date, error = %e, "Failed to remove day from index");
+            }
+        }
+    }
+    audited?;
+
+    Ok(Json(json!({
+        "status": "success",
+        "deleted_folders": dates.len(),
+        "deleted_files": deleted_files,
+        "dates": dates
+    })))
+}
@@ -0,0 +1,897 @@
+use std::{collections::{BTreeMap, BTreeSet}, path::Path, sync::Arc};
+
+use axum::{
+    Json,
+    body::Body,
+    extract::{Path as UrlPath, Query, Request, State},
+    http::{HeaderMap, HeaderValue, StatusCode, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::Deserialize;
+use serde_json::{Value, json};
+use sha2::{Digest, Sha256};
+use tokio_util::io::{ReaderStream, SyncIoBridge};
+use utoipa::IntoParams;
+use walkdir::DirEntry;
+
+use crate::{ApiError, AppState, audit, client::ClientIp, retention, tenant::Tenant, storage::{self, StorageBackend, StoredFile}, upload};
+
+// Uploads are grouped into one folder per day, named YYYY-MM-DD
+pub fn parse_day(name: &str) -> Option<NaiveDate> {
+    if name.len() != 10 {
+        return None;
+    }
+
+    NaiveDate::parse_from_str(name, "%Y-%m-%d").ok()
+}
+
+pub fn parse_day_param(value: &str) -> Result<NaiveDate, ApiError> {
+    parse_day(value)
+        .ok_or_else(|| ApiError::BadRequest(format!("Invalid date, expected YYYY-MM-DD: {}", value)))
+}
+
+// Inclusive range of day folders, either bound may be open
+#[derive(Clone, Copy, Default)]
+pub struct DayRange {
+    pub from: Option<NaiveDate>,
+    pub to: Option<NaiveDate>,
+}
+
+impl DayRange {
+    pub fn parse(from: Option<&str>, to: Option<&str>) -> Result<Self, ApiError> {
+        let range = Self {
+            from: from.map(parse_day_param).transpose()?,
+            to: to.map(parse_day_param).transpose()?,
+        };
+
+        if let (Some(from), Some(to)) = (range.from, range.to)
+            && from > to
+        {
+            return Err(ApiError::BadRequest("from must not be after to".to_string()));
+        }
+
+        Ok(range)
+    }
+
+    pub fn is_unbounded(&self) -> bool {
+        self.from.is_none() && self.to.is_none()
+    }
+
+    pub fn contains_day(&self, day: NaiveDate) -> bool {
+        self.from.is_none_or(|from| day >= from) && self.to.is_none_or(|to| day <= to)
+    }
+
+    // Checks the day folder a relative path lives in. Files outside of day folders only
+    // match an unbounded range.
+    pub fn contains(&self, relative: &str) -> bool {
+        if self.is_unbounded() {
+            return true;
+        }
+
+        relative
+            .split('/')
+            .next()
+            .and_then(parse_day)
+            .is_some_and(|day| self.contains_day(day))
+    }
+}
+
+// Accepts unix seconds or an RFC3339 timestamp
+pub fn parse_since(value: &str) -> Result<DateTime<Utc>, ApiError> {
+    let parsed = match value.parse::<i64>() {
+        Ok(seconds) => DateTime::from_timestamp(seconds, 0),
+        Err(_) => DateTime::parse_from_rfc3339(value).ok().map(|t| t.to_utc()),
+    };
+
+    parsed.ok_or_else(|| ApiError::BadRequest(format!("Invalid since, expected unix seconds or RFC3339: {}", value)))
+}
+
+// Whether a file was uploaded strictly after `since`. Stored names start with the upload's unix
+// timestamp, which is more reliable than an mtime. That prefix only has whole seconds, so within
+// the same second, and for files without one, the mtime decides.
+pub fn uploaded_after(file: &StoredFile, since: DateTime<Utc>) -> bool {
+    let prefix = file.key
+        .rsplit('/')
+        .next()
+        .and_then(|name| name.split_once('_'))
+        .and_then(|(timestamp, _)| timestamp.parse::<i64>().ok());
+
+    if let Some(timestamp) = prefix
+        && timestamp != since.timestamp()
+    {
+        return timestamp > since.timestamp();
+    }
+
+    file.modified.is_some_and(|modified| DateTime::<Utc>::from(modified) > since)
+}
+
+// Every regular file below `data_dir` in a stable order, paired with its path relative to it.
+// Symlinks are never followed, they could point anywhere outside of the data dir.
+pub fn walk(data_dir: &Path) -> impl Iterator<Item = (DirEntry, String)> + '_ {
+    walkdir::WalkDir::new(data_dir)
+        .follow_links(false)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_entry(|e| {
+            if e.depth() == 0 {
+                return true;
+            }
+
+            // Dot files are in-progress uploads and other internal bookkeeping
+            if e.file_name().to_string_lossy().starts_with('.') {
+                return false;
+            }
+
+            if e.path_is_symlink() {
+                tracing::warn!(path = %e.path().display(), "Skipping symlink in data directory");
+                return false;
+            }
+
+            true
+        })
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+        .filter_map(move |e| {
+            let relative = e.path().strip_prefix(data_dir).ok()?.to_string_lossy().to_string();
+            Some((e, relative))
+        })
+}
+
+// Number of files and their total size below `dir`
+pub fn usage(dir: &Path) -> (usize, u64) {
+    walk(dir)
+        .filter_map(|(entry, _)| entry.metadata().ok())
+        .fold((0, 0), |(count, bytes), metadata| (count + 1, bytes + metadata.len()))
+}
+
+// Stored files grouped by the day folder they're in, files outside of one are left out. A
+// tenant that never uploaded has no files rather than a missing folder.
+pub fn stored_days(storage: &dyn StorageBackend) -> std::io::Result<BTreeMap<String, Vec<StoredFile>>> {
+    let stored = match storage.list() {
+        Ok(stored) => stored,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+        Err(e) => return Err(e),
+    };
+
+    let mut days: BTreeMap<String, Vec<StoredFile>> = BTreeMap::new();
+    for file in stored {
+        if let Some((folder, _)) = file.key.split_once('/')
+            && parse_day(folder).is_some()
+        {
+            days.entry(folder.to_string()).or_default().push(file);
+        }
+    }
+
+    Ok(days)
+}
+
+const DEFAULT_PAGE_SIZE: usize = 100;
+const MAX_PAGE_SIZE: usize = 1000;
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ListQuery {
+    /// Files per page, 100 by default and at most 1000
+    limit: Option<usize>,
+    /// Number of files to skip
+    #[serde(default)]
+    offset: usize,
+    /// Include the metadata recorded at upload time
+    #[serde(default)]
+    meta: bool,
+    /// First day to list, as YYYY-MM-DD
+    from: Option<String>,
+    /// Last day to list, as YYYY-MM-DD
+    to: Option<String>,
+    /// Only files whose recorded content type starts with this, like text/ or text/plain.
+    /// Files without one count as application/octet-stream.
+    content_type: Option<String>,
+}
+
+// Lets clients reuse listings for a little while, they change with every upload. Errors are
+// left alone so a failed request is retried right away.
+pub async fn cache_listings(State(state): State<Arc<AppState>>, request: Request, next: Next) -> Response {
+    let mut response = next.run(request).await;
+    if response.status().is_success()
+        && let Ok(value) = HeaderValue::from_str(&state.config.listing_cache_control())
+    {
+        response.headers_mut().insert(header::CACHE_CONTROL, value);
+    }
+
+    response
+}
+
+// Files without a recorded type are matched as what they'd be served as at worst
+fn content_type_matches(content_type: Option<&str>, prefix: &str) -> bool {
+    content_type
+        .unwrap_or("application/octet-stream")
+        .to_ascii_lowercase()
+        .starts_with(&prefix.to_ascii_lowercase())
+}
+
+// The day range and content type a listing is narrowed to
+struct ListFilter {
+    range: DayRange,
+    content_type: Option<String>,
+}
+
+impl ListFilter {
+    fn parse(query: &ListQuery) -> Result<Self, ApiError> {
+        Ok(Self {
+            range: DayRange::parse(query.from.as_deref(), query.to.as_deref())?,
+            content_type: query.content_type.clone().filter(|content_type| !content_type.is_empty()),
+        })
+    }
+
+    #[cfg(feature = "sqlite")]
+    fn is_unbounded(&self) -> bool {
+        self.range.is_unbounded() && self.content_type.is_none()
+    }
+
+    #[cfg(feature = "sqlite")]
+    fn matches_record(&self, record: &crate::index::Record) -> bool {
+        self.range.contains(&record.path)
+            && self.content_type.as_deref().is_none_or(|wanted| {
+                content_type_matches(record.content_type.as_deref(), wanted)
+            })
+    }
+
+    // Without an index the type is only known from each file's metadata
+    fn matches(&self, storage: &dyn StorageBackend, file: &StoredFile) -> bool {
+        self.range.contains(&file.key)
+            && self.content_type.as_deref().is_none_or(|wanted| {
+                content_type_matches(stored_content_type(storage, &file.key).as_deref(), wanted)
+            })
+    }
+}
+
+// Files are listed in path order, which is stable across requests since names start with
+// the day and upload time
+#[utoipa::path(
+    get,
+    path = "/files",
+    params(ListQuery),
+    responses(
+        (status = 200, body = crate::openapi::FilePage),
+        (status = 400, description = "Invalid date", body = crate::openapi::ErrorBody),
+        (status = 401, description = "Missing or invalid bearer token", body = crate::openapi::ErrorBody),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn list_files(
+    State(state): State<Arc<AppState>>,
+    tenant: Tenant,
+    Query(query): Query<ListQuery>,
+) -> Result<impl IntoResponse, ApiError> {
+    let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE).min(MAX_PAGE_SIZE);
+    let offset = query.offset;
+    let filter = ListFilter::parse(&query)?;
+    let storage = state.storage_for(&tenant);
+
+    // The index already knows every file, no need to walk the tree
+    #[cfg(feature = "sqlite")]
+    if state.index.is_some() {
+        let (records, total) = tokio::task::spawn_blocking(move || {
+            let prefix = tenant.prefix();
+            let Some(index) = state.index.as_ref() else {
+                return Ok((Vec::new(), 0));
+            };
+            if filter.is_unbounded() {
+                return index.page(&prefix, limit, offset);
+            }
+
+            let records = index.filtered(&prefix, |record| filter.matches_record(record))?;
+            let total = records.len();
+            Ok((records.into_iter().skip(offset).take(limit).collect(), total))
+        })
+        .await
+        .map_err(|e| ApiError::InternalError(format!("Failed to list files: {}", e)))?
+        .map_err(|e| ApiError::InternalError(format!("Failed to read index: {}", e)))?;
+
+        let files = records.iter().map(crate::index::Record::describe).collect();
+        return page(&storage, &query, files, total, limit).await;
+    }
+
+    let stored = storage::blocking(&storage, move |storage| {
+        Ok(storage.list()?
+            .into_iter()
+            .filter(|file| filter.matches(storage, file))
+            .collect::<Vec<_>>())
+    })
+    .await
+    .map_err(|e| storage::api_error("Failed to list files", e))?;
+    let files = stored.iter()
+        .skip(offset)
+        .take(limit)
+        .map(StoredFile::describe)
+        .collect();
+
+    page(&storage, &query, files, stored.len(), limit).await
+}
+
+// Quotes a CSV field when it holds anything that would break the row apart
+fn csv_field(value: &str) -> String {
+    match value.contains([',', '"', '\n', '\r']) {
+        true => format!("\"{}\"", value.replace('"', "\"\"")),
+        false => value.to_string(),
+    }
+}
+
+// The same files as /files, as one spreadsheet. Unless limit or offset are given every matching
+// file is exported. The sha256 column is only filled for files whose checksum was recorded.
+#[utoipa::path(
+    get,
+    path = "/files.csv",
+    params(ListQuery),
+    responses(
+        (status = 200, description = "path,size,modified,content_type,sha256 with a header row", content_type = "text/csv", body = String),
+        (status = 400, description = "Invalid date", body = crate::openapi::ErrorBody),
+        (status = 401, description = "Missing or invalid bearer token", body = crate::openapi::ErrorBody),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn list_files_csv(
+    State(state): State<Arc<AppState>>,
+    tenant: Tenant,
+    Query(query): Query<ListQuery>,
+) -> Result<impl IntoResponse, ApiError> {
+    let limit = query.limit.unwrap_or(usize::MAX);
+    let offset = query.offset;
+    let filter = ListFilter::parse(&query)?;
+    let storage = state.storage_for(&tenant);
+
+    // The index already knows every file along with its type and checksum
+    #[cfg(feature = "sqlite")]
+    if state.index.is_some() {
+        let rows = tokio::task::spawn_blocking(move || {
+            let Some(index) = state.index.as_ref() else {
+                return Ok(Vec::new());
+            };
+
+            Ok(index.filtered(&tenant.prefix(), |record| filter.matches_record(record))?
+                .into_iter()
+                .skip(offset)
+                .take(limit)
+                .map(|record| {
+                    let modified = DateTime::<Utc>::from_timestamp(record.uploaded_at, 0).map(|t| t.to_rfc3339());
+                    CsvRow { path: record.path, size: record.size, modified, content_type: record.content_type, sha256: record.sha256 }
+                })
+                .collect())
+        })
+        .await
+        .map_err(|e| ApiError::InternalError(format!("Failed to list files: {}", e)))?
+        .map_err(|e: rusqlite::Error| ApiError::InternalError(format!("Failed to read index: {}", e)))?;
+
+        return Ok(csv_response(rows));
+    }
+
+    let rows = storage::blocking(&storage, move |storage| {
+        Ok(storage.list()?
+            .into_iter()
+            .filter(|file| filter.matches(storage, file))
+            .skip(offset)
+            .take(limit)
+            .map(|file| CsvRow {
+                modified: file.modified.map(|t| DateTime::<Utc>::from(t).to_rfc3339()),
+                content_type: stored_content_type(storage, &file.key),
+                sha256: recorded_checksum(storage, &file.key),
+                path: file.key,
+                size: file.size,
+            })
+            .collect())
+    })
+    .await
+    .map_err(|e| storage::api_error("Failed to list files", e))?;
+
+    Ok(csv_response(rows))
+}
+
+struct CsvRow {
+    path: String,
+    size: u64,
+    modified: Option<String>,
+    content_type: Option<String>,
+    sha256: Option<String>,
+}
+
+fn csv_response(rows: Vec<CsvRow>) -> Response {
+    let mut csv = String::from("path,size,modified,content_type,sha256\r\n");
+    for row in rows {
+        let fields = [
+            csv_field(&row.path),
+            row.size.to_string(),
+            row.modified.unwrap_or_default(),
+            csv_field(&row.content_type.unwrap_or_default()),
+            row.sha256.unwrap_or_default(),
+        ];
+        csv.push_str(&fields.join(","));
+        csv.push_str("\r\n");
+    }
+
+    (
+        [
+            (header::CONTENT_TYPE, "text/csv; charset=utf-8"),
+            (header::CONTENT_DISPOSITION, "attachment; filename=\"files.csv\""),
+        ],
+        csv,
+    ).into_response()
+}
+
+async fn page(
+    storage: &Arc<dyn StorageBackend>,
+    query: &ListQuery,
+    files: Vec<Value>,
+    total: usize,
+    limit: usize,
+) -> Result<Json<Value>, ApiError> {
+    let files = match query.meta {
+        true => attach_meta(storage, files).await?,
+        false => files,
+    };
+
+    Ok(Json(json!({
+        "total": total,
+        "limit": limit,
+        "offset": query.offset,
+        "files": files
+    })))
+}
+
+// Day folders in order, with the number and total size of their files, followed by archived
+// days merged in by date. Other top level folders, like those of tenants when listing without
+// one, are only named.
+#[utoipa::path(
+    get,
+    path = "/dates",
+    responses(
+        (status = 200, body = crate::openapi::Dates),
+        (status = 401, description = "Missing or invalid bearer token", body = crate::openapi::ErrorBody),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn list_dates(
+    State(state): State<Arc<AppState>>,
+    tenant: Tenant,
+) -> Result<impl IntoResponse, ApiError> {
+    let stored = storage::blocking(&state.storage_for(&tenant), |storage| storage.list()).await
+        .map_err(|e| storage::api_error("Failed to list dates", e))?;
+
+    let mut days: BTreeMap<String, (Option<usize>, u64, bool)> = BTreeMap::new();
+    let mut other = BTreeSet::new();
+    for file in &stored {
+        let Some((folder, _)) = file.key.split_once('/') else {
+            continue;
+        };
+
+        if parse_day(folder).is_some() {
+            let (count, bytes, _) = days.entry(folder.to_string()).or_insert((Some(0), 0, false));
+            *count = count.map(|count| count + 1);
+            *bytes += file.size;
+        } else {
+            other.insert(folder);
+        }
+    }
+
+    // Days the retention sweep packed away, a folder for the same day still wins since that's
+    // what gets downloaded
+    if let Some(archive_dir) = state.archive_dir_for(&tenant) {
+        let archived = tokio::task::spawn_blocking(move || retention::archived_days(&archive_dir)).await
+            .map_err(|e| ApiError::InternalError(format!("Failed to list archived days: {}", e)))?;
+        for (date, count, bytes) in archived {
+            days.entry(date).or_insert((count, bytes, true));
+        }
+    }
+
+    let dates: Vec<_> = days.into_iter()
+        .map(|(date, (count, bytes, archived))| json!({ "date": date, "files": count, "total_bytes": bytes, "archived": archived }))
+        .collect();
+
+    Ok(Json(json!({
+        "dates": dates,
+        "other": other
+    })))
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct SearchQuery {
+    /// Part of the stored or the original file name, ignoring case
+    q: Option<String>,
+    /// First day to search, as YYYY-MM-DD
+    from: Option<String>,
+    /// Last day to search, as YYYY-MM-DD
+    to: Option<String>,
+}
+
+// Finds files whose stored name or original name contains `q`, ignoring case. The original
+// name, as uploaded before sanitizing, comes from the upload metadata. Files stored before that
+// was recorded only match on their stored name.
+#[utoipa::path(
+    get,
+    path = "/search",
+    params(SearchQuery),
+    responses(
+        (status = 200, body = Vec<crate::openapi::FileEntry>),
+        (status = 400, description = "Missing search term or invalid date", body = crate::openapi::ErrorBody),
+        (status = 401, description = "Missing or invalid bearer token", body = crate::openapi::ErrorBody),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn search(
+    State(state): State<Arc<AppState>>,
+    tenant: Tenant,
+    Query(query): Query<SearchQuery>,
+) -> Result<impl IntoResponse, ApiError> {
+    let needle = query.q
+        .filter(|q| !q.is_empty())
+        .ok_or_else(|| ApiError::BadRequest("Missing search term q".to_string()))?;
+    let range = DayRange::parse(query.from.as_deref(), query.to.as_deref())?;
+
+    #[cfg(feature = "sqlite")]
+    if state.index.is_some() {
+        let records = tokio::task::spawn_blocking(move || {
+            let prefix = tenant.prefix();
+            state.index.as_ref().map_or(Ok(Vec::new()), |index| index.search(&prefix, &needle))
+        })
+        .await
+        .map_err(|e| ApiError::InternalError(format!("Failed to search files: {}", e)))?
+        .map_err(|e| ApiError::InternalError(format!("Failed to read index: {}", e)))?;
+
+        return Ok(Json(records.iter()
+            .filter(|record| range.contains(&record.path))
+            .map(crate::index::Record::describe)
+            .collect()));
+    }
+
+    let needle = needle.to_lowercase();
+    let matches = move |name: &str| name.to_lowercase().contains(&needle);
+    let found = storage::blocking(&state.storage_for(&tenant), move |storage| {
+        Ok(storage.list()?
+            .into_iter()
+            .filter(|file| range.contains(&file.key))
+            .filter(|file| {
+                matches(file.key.rsplit('/').next().unwrap_or_default())
+                    || read_meta(storage, &file.key).is_ok_and(|meta| meta["original_name"].as_str().is_some_and(&matches))
+            })
+            .map(|file| file.describe())
+            .collect::<Vec<_>>())
+    })
+    .await
+    .map_err(|e| storage::api_error("Failed to search files", e))?;
+
+    Ok(Json(found))
+}
+
+// Parses a single `bytes=` range into inclusive offsets. Multiple or malformed ranges are
+// ignored and the whole file is served, which the spec allows.
+fn parse_range(value: &str, len: u64) -> Result<Option<(u64, u64)>, ApiError> {
+    let Some(spec) = value.trim().strip_prefix("bytes=") else {
+        return Ok(None);
+    };
+    let Some((start, end)) = spec.split_once('-').filter(|_| !spec.contains(',')) else {
+        return Ok(None);
+    };
+
+    let (start, end) = match (start.trim(), end.trim()) {
+        ("", suffix) => match suffix.parse::<u64>() {
+            Ok(0) => return Err(ApiError::RangeNotSatisfiable(len)),
+            Ok(suffix) => (len.saturating_sub(suffix), len.saturating_sub(1)),
+            Err(_) => return Ok(None),
+        },
+        (start, end) => {
+            let Ok(start) = start.parse::<u64>() else {
+                return Ok(None);
+            };
+            let end = match end {
+                "" => len.saturating_sub(1),
+                end => match end.parse::<u64>() {
+                    Ok(end) if end >= start => end.min(len.saturating_sub(1)),
+                    _ => return Ok(None),
+                },
+            };
+            (start, end)
+        }
+    };
+
+    if start >= len {
+        return Err(ApiError::RangeNotSatisfiable(len));
+    }
+
+    Ok(Some((start, end)))
+}
+
+// Checksums are kept in a hidden sidecar next to the file, e.g. `2024-01-31/.1706659200_a.tsv.sha256`
+// Sidecars are hidden, so walks and archives never pick them up as files of their own
+fn sidecar_key(key: &str, extension: &str) -> String {
+    match key.rsplit_once('/') {
+        Some((dir, name)) => format!("{}/.{}.{}", dir, name, extension),
+        None => format!(".{}.{}", key, extension),
+    }
+}
+
+pub fn checksum_key(key: &str) -> String {
+    sidecar_key(key, "sha256")
+}
+
+pub fn meta_key(key: &str) -> String {
+    sidecar_key(key, "meta.json")
+}
+
+// Upload details recorded next to the file, files stored before those were recorded have none
+fn read_meta(storage: &dyn StorageBackend, key: &str) -> std::io::Result<Value> {
+    let reader = storage.read(&meta_key(key))?;
+    serde_json::from_reader(reader).map_err(std::io::Error::other)
+}
+
+// Adds the recorded metadata to described files, as null where there is none
+async fn attach_meta(storage: &Arc<dyn StorageBackend>, files: Vec<Value>) -> Result<Vec<Value>, ApiError> {
+    storage::blocking(storage, move |storage| {
+        Ok(files.into_iter()
+            .map(|mut file| {
+                let meta = file["path"].as_str().and_then(|key| read_meta(storage, key).ok());
+                file["meta"] = meta.unwrap_or(Value::Null);
+                file
+            })
+            .collect())
+    })
+    .await
+    .map_err(|e| ApiError::InternalError(format!("Failed to read metadata: {}", e)))
+}
+
+async fn file_meta(state: &AppState, tenant: &Tenant, relative: &str) -> Result<Response, ApiError> {
+    let key = relative.to_string();
+    let meta = storage::blocking(&state.storage_for(tenant), move |storage| read_meta(storage, &key)).await
+        .map_err(|e| storage::api_error("Failed to read metadata", e))?;
+
+    Ok(Json(meta).into_response())
+}
+
+// The type the file was uploaded with. Files stored before that was recorded, or uploaded
+// without one, get None.
+async fn recorded_content_type(state: &AppState, tenant: &Tenant, relative: &str) -> Option<String> {
+    let key = relative.to_string();
+    storage::blocking(&state.storage_for(tenant), move |storage| Ok(stored_content_type(storage, &key))).await
+        .ok()
+        .flatten()
+        .filter(|content_type| HeaderValue::from_str(content_type).is_ok())
+}
+
+fn stored_content_type(storage: &dyn StorageBackend, key: &str) -> Option<String> {
+    let meta = read_meta(storage, key).ok()?;
+    meta["content_type"].as_str().map(str::to_string)
+}
+
+// Checksum recorded at upload time, without hashing files that have none
+pub fn recorded_checksum(storage: &dyn StorageBackend, key: &str) -> Option<String> {
+    use std::io::Read;
+
+    let mut stored = String::new();
+    storage.read(&checksum_key(key)).ok()?.read_to_string(&mut stored).ok()?;
+    Some(stored.trim().to_string())
+}
+
+fn read_checksum(storage: &dyn StorageBackend, key: &str) -> std::io::Result<String> {
+    use std::io::Read;
+
+    let mut stored = String::new();
+    match storage.read(&checksum_key(key)) {
+        Ok(mut reader) => {
+            reader.read_to_string(&mut stored)?;
+            return Ok(stored.trim().to_string());
+        }
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+        Err(_) => {}
+    }
+
+    // Files stored before checksums were recorded
+    sha256(storage.read(key)?)
+}
+
+// Hex encoded SHA-256 of everything `reader` yields
+pub fn sha256(mut reader: impl std::io::Read) -> std::io::Result<String> {
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; 64 * 1024];
+    loop {
+        let read = reader.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+
+    Ok(hex::encode(hasher.finalize()))
+}
+
+async fn file_checksum(state: &AppState, tenant: &Tenant, relative: &str) -> Result<Response, ApiError> {
+    let key = relative.to_string();
+    let sha256 = storage::blocking(&state.storage_for(tenant), move |storage| read_checksum(storage, &key)).await
+        .map_err(|e| storage::api_error("Failed to read checksum", e))?;
+
+    Ok(Json(json!({
+        "path": relative,
+        "sha256": sha256
+    })).into_response())
+}
+
+// Streams a blocking reader, stored files may well be larger than memory
+fn stream_reader(reader: impl std::io::Read + Send + 'static) -> Body {
+    let (body, writer) = tokio::io::duplex(64 * 1024);
+    let mut writer = SyncIoBridge::new(writer);
+    tokio::task::spawn_blocking(move || {
+        let mut reader = reader;
+        if let Err(e) = std::io::copy(&mut reader, &mut writer) {
+            tracing::error!(error = %e, "Failed to stream file");
+        }
+    });
+
+    Body::from_stream(ReaderStream::new(body))
+}
+
+// Files are mostly fetched as DATE/NAME. A first segment shaped like a date has to be a real
+// one, and the name a stored name exactly, which also keeps the hidden sidecars out of reach.
+fn check_day_path(relative: &str) -> Result<(), ApiError> {
+    let Some((folder, name)) = relative.split_once('/') else {
+        return Ok(());
+    };
+    let looks_like_day = folder.len() == 10 && folder.bytes().all(|b| b.is_ascii_digit() || b == b'-');
+    if !looks_like_day || name.contains('/') {
+        return Ok(());
+    }
+
+    parse_day_param(folder)?;
+    if upload::sanitize_filename(name).as_deref() != Some(name) {
+        return Err(ApiError::BadRequest(format!("Invalid file name: {}", name)));
+    }
+
+    Ok(())
+}
+
+#[utoipa::path(
+    get,
+    path = "/files/{path}",
+    description = "A single stored file with the content type it was uploaded with, supports Range requests. Usually addressed as DATE/NAME. Appending /checksum or /meta to the path returns its SHA-256 or upload metadata instead",
+    params(("path" = String, Path, description = "Path as listed by /files, like 2024-05-01/1714550400_session.log")),
+    responses(
+        (status = 200, content_type = "application/octet-stream", body = Vec<u8>),
+        (status = 206, description = "The requested range", content_type = "application/octet-stream", body = Vec<u8>),
+        (status = 400, description = "Invalid date or file name", body = crate::openapi::ErrorBody),
+        (status = 401, description = "Missing or invalid bearer token", body = crate::openapi::ErrorBody),
+        (status = 404, description = "No such file", body = crate::openapi::ErrorBody),
+        (status = 416, description = "Range outside of the file", body = crate::openapi::ErrorBody),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn download_file(
+    State(state): State<Arc<AppState>>,
+    client: ClientIp,
+    tenant: Tenant,
+    UrlPath(relative): UrlPath<String>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    // Stored names always start with a timestamp, so no file is literally called `checksum` or `meta`
+    if let Some(file) = relative.strip_suffix("/checksum") {
+        return file_checksum(&state, &tenant, file).await;
+    }
+    if let Some(file) = relative.strip_suffix("/meta") {
+        return file_meta(&state, &tenant, file).await;
+    }
+
+    check_day_path(&relative)?;
+    let storage = state.storage_for(&tenant);
+    let key = relative.clone();
+    let len = storage::blocking(&storage, move |storage| storage.stat(&key)).await
+        .map_err(|e| storage::api_error("Failed to read file metadata", e))?
+        .size;
+    let range = match headers.get(header::RANGE).and_then(|v| v.to_str().ok()) {
+        Some(value) => parse_range(value, len)?,
+        None => None,
+    };
+
+    audit::record(&state, "download", &client, &tenant, vec![relative.clone()])?;
+
+    let filename = relative.rsplit('/').next().unwrap_or_default();
+    let content_type = match recorded_content_type(&state, &tenant, &relative).await {
+        Some(content_type) => content_type,
+        None => mime_guess::from_path(filename).first_or_octet_stream().to_string(),
+    };
+    state.metrics.downloads.inc();
+    state.metrics.download_size.observe(range.map_or(len, |(start, end)| end - start + 1) as f64);
+
+    let response = Response::builder()
+        .header(header::CONTENT_TYPE, content_type)
+        .header(header::ACCEPT_RANGES, "bytes")
+        .header(
+            header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{}\"", filename)
+        );
+
+    let (start, served) = range.map_or((0, len), |(start, end)| (start, end - start + 1));
+    let key = relative.clone();
+    let reader = storage::blocking(&storage, move |storage| storage.open(&key, start)).await
+        .map_err(|e| storage::api_error("Failed to read file", e))?;
+    let body = stream_reader(std::io::Read::take(reader, served));
+
+    let response = match range {
+        Some((start, end)) => response
+            .status(StatusCode::PARTIAL_CONTENT)
+            .header(header::CONTENT_RANGE, format!("bytes {}-{}/{}", start, end, len))
+            .header(header::CONTENT_LENGTH, served)
+            .body(body),
+        None => response
+            .status(StatusCode::OK)
+            .header(header::CONTENT_LENGTH, len)
+            .body(body),
+    };
+
+    response.map_err(|e| ApiError::InternalError(format!("Failed to build response: {}", e)))
+}
+
+// Deletes a stored file along with its sidecars, index entry and, unless it's today's, the
+// day folder it leaves empty. Returns the size of the file.
+pub async fn remove_stored(state: &AppState, tenant: &Tenant, key: &str) -> std::io::Result<u64> {
+    let storage = state.storage_for(tenant);
+    let deleted = key.to_string();
+    let size = storage::blocking(&storage, move |storage| storage.delete(&deleted)).await?;
+    state.release_used_bytes(size);
+
+    for sidecar in [checksum_key(key), meta_key(key)] {
+        let deleted = sidecar.clone();
+        if let Err(e) = storage::blocking(&storage, move |storage| storage.delete(&deleted)).await
+            && e.kind() != std::io::ErrorKind::NotFound
+        {
+            tracing::warn!(path = %sidecar, error = %e, "Failed to delete sidecar");
+        }
+    }
+
+    // The day folder goes with its last file, unless it's today's and uploads may be headed there
+    if let Some((folder, name)) = key.split_once('/')
+        && !name.contains('/')
+        && parse_day(folder).is_some_and(|day| day != state.config.now().date_naive())
+        && let Err(e) = crate::retention::remove_if_empty(&state.data_dir_for(tenant).join(folder))
+        && e.kind() != std::io::ErrorKind::NotFound
+    {
+        tracing::warn!(path = %folder, error = %e, "Failed to remove empty day folder");
+    }
+
+    #[cfg(feature = "sqlite")]
+    if let Some(index) = &state.index
+        && let Err(e) = index.remove(&tenant.scope(key))
+    {
+        tracing::error!(path = %key, error = %e, "Failed to remove file from index");
+    }
+
+    Ok(size)
+}
+
+#[utoipa::path(
+    delete,
+    path = "/files/{path}",
+    params(("path" = String, Path, description = "Path as listed by /files")),
+    responses(
+        (status = 204, description = "Deleted"),
+        (status = 401, description = "Missing or invalid bearer token", body = crate::openapi::ErrorBody),
+        (status = 404, description = "No such file", body = crate::openapi::ErrorBody),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn delete_file(
+    State(state): State<Arc<AppState>>,
+    client: ClientIp,
+    tenant: Tenant,
+    UrlPath(relative): UrlPath<String>,
+) -> Result<impl IntoResponse, ApiError> {
+    let key = Path::new(&relative)
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/");
+
+    let size = remove_stored(&state, &tenant, &key).await
+        .map_err(|e| storage::api_error("Failed to delete file", e))?;
+    tracing::info!(path = %tenant.scope(&key), size, %client, "File deleted");
+    audit::record(&state, "delete", &client, &tenant, vec![key])?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,88 @@
+use std::{path::Path, sync::Arc};
+
+use axum::{Json, extract::State, http::StatusCode, response::IntoResponse};
+use serde_json::json;
+
+use crate::AppState;
+
+// Creates and removes a probe file to make sure uploads can actually be stored
+pub fn probe_writable(data_dir: &Path) -> std::io::Result<()> {
+    let probe = data_dir.join(format!(".health_{}", uuid::Uuid::new_v4()));
+    std::fs::write(&probe, b"ok")?;
+    std::fs::remove_file(&probe)
+}
+
+// Liveness only says the process responds, it never touches the disk
+#[utoipa::path(
+    get,
+    path = "/health",
+    description = "Whether the process responds. Also served at /livez",
+    responses((status = 200, body = crate::openapi::Status)),
+)]
+pub async fn livez(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    Json(json!({
+        "status": "ok",
+        "message": "Server is running :)",
+        "uptime_seconds": state.started.elapsed().as_secs()
+    }))
+}
+
+// Readiness additionally requires a writable data dir with enough free space
+#[utoipa::path(
+    get,
+    path = "/readyz",
+    responses(
+        (status = 200, body = crate::openapi::Readiness),
+        (status = 503, description = "Data dir not writable or low on space", body = crate::openapi::Readiness),
+    ),
+)]
+pub async fn readyz(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    let data_dir = state.config.data_dir.clone();
+    let min_free_bytes = state.config.min_free_bytes;
+
+    let (writable, available_bytes) = tokio::task::spawn_blocking(move || {
+        let writable = probe_writable(&data_dir)
+            .inspect_err(|e| tracing::warn!(error = %e, "Data directory is not writable"))
+            .is_ok();
+        let available_bytes = fs4::available_space(&data_dir).ok();
+        (writable, available_bytes)
+    })
+    .await
+    .unwrap_or((false, None));
+
+    let enough_space = available_bytes.is_some_and(|bytes| bytes >= min_free_bytes);
+
+    if writable && enough_space {
+        return (StatusCode::OK, Json(json!({
+            "status": "ok",
+            "message": "Server is running :)",
+            "writable": writable,
+            "available_bytes": available_bytes
+        })));
+    }
+
+    (StatusCode::SERVICE_UNAVAILABLE, Json(json!({
+        "status": "degraded",
+        "message": "The data directory is not writable or running out of space",
+        "writable": writable,
+        "available_bytes": available_bytes
+    })))
+}
+
+// Identifies the running build, so a rollout can be confirmed on every node
+#[utoipa::path(
+    get,
+    path = "/version",
+    responses((status = 200, body = crate::openapi::Version)),
+)]
+pub async fn version() -> impl IntoResponse {
+    let built_at = env!("EOTW_BUILD_TIMESTAMP").parse::<i64>().ok()
+        .and_then(|secs| chrono::DateTime::from_timestamp(secs, 0))
+        .map(|built_at| built_at.to_rfc3339());
+
+    Json(json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "commit": env!("EOTW_GIT_COMMIT"),
+        "built_at": built_at
+    }))
+}
@@ -0,0 +1,187 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    body::{Body, Bytes},
+    extract::{Request, State},
+    http::{HeaderValue, Method, StatusCode, header},
+    middleware::Next,
+    response::Response,
+};
+
+use crate::{ApiError, AppState, tenant::Tenant};
+
+pub const HEADER: &str = "idempotency-key";
+const REPLAYED_HEADER: &str = "idempotent-replayed";
+
+// Upper bound on remembered keys, so a client inventing a key per request can't grow the map forever
+const MAX_TRACKED_KEYS: usize = 10_000;
+const MAX_KEY_LEN: usize = 255;
+// Upload responses are small JSON documents, anything bigger isn't worth remembering
+const MAX_CACHED_BODY: usize = 1024 * 1024;
+
+struct Entry {
+    created: Instant,
+    // None while the first request with the key is still running
+    response: Option<(StatusCode, Bytes)>,
+}
+
+enum Lookup {
+    New,
+    InProgress,
+    Done(StatusCode, Bytes),
+}
+
+// What a response is remembered by. The parts are kept apart, joining them into a path would
+// let the root tenant's `acme/x` stand in for tenant acme's `x`. A key reused for a request to
+// another route or file name is a different upload and isn't answered with this one.
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct Key {
+    tenant: Option<String>,
+    method: Method,
+    path: String,
+    key: String,
+}
+
+// Remembers the responses of successful uploads by their Idempotency-Key for `ttl`
+pub struct IdempotencyCache {
+    ttl: Duration,
+    entries: Mutex<HashMap<Key, Entry>>,
+}
+
+impl IdempotencyCache {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn entries(&self) -> std::sync::MutexGuard<'_, HashMap<Key, Entry>> {
+        self.entries.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    // Looks up `key`, claiming it for the caller when nobody used it yet
+    fn begin(&self, key: &Key) -> Lookup {
+        let now = Instant::now();
+        let mut entries = self.entries();
+
+        if let Some(entry) = entries.get(key)
+            && now.duration_since(entry.created) < self.ttl
+        {
+            return match &entry.response {
+                Some((status, body)) => Lookup::Done(*status, body.clone()),
+                None => Lookup::InProgress,
+            };
+        }
+
+        if entries.len() >= MAX_TRACKED_KEYS && !entries.contains_key(key) {
+            evict(&mut entries, now, self.ttl);
+        }
+
+        entries.insert(key.clone(), Entry { created: now, response: None });
+        Lookup::New
+    }
+
+    fn finish(&self, key: &Key, status: StatusCode, body: Bytes) {
+        if let Some(entry) = self.entries().get_mut(key) {
+            entry.response = Some((status, body));
+        }
+    }
+
+    fn abandon(&self, key: &Key) {
+        let mut entries = self.entries();
+        if entries.get(key).is_some_and(|entry| entry.response.is_none()) {
+            entries.remove(key);
+        }
+    }
+}
+
+// Drops expired keys. If all of them are still valid the oldest one goes.
+fn evict(entries: &mut HashMap<Key, Entry>, now: Instant, ttl: Duration) {
+    entries.retain(|_, entry| now.duration_since(entry.created) < ttl);
+
+    if entries.len() >= MAX_TRACKED_KEYS
+        && let Some(oldest) = entries.iter().min_by_key(|(_, e)| e.created).map(|(key, _)| key.clone())
+    {
+        entries.remove(&oldest);
+    }
+}
+
+// Releases a claimed key if the request ends without a response to remember, including when
+// it's dropped halfway, so a retry isn't locked out
+struct Claim<'a> {
+    cache: &'a IdempotencyCache,
+    key: &'a Key,
+    done: bool,
+}
+
+impl Drop for Claim<'_> {
+    fn drop(&mut self) {
+        if !self.done {
+            self.cache.abandon(self.key);
+        }
+    }
+}
+
+fn json_response(status: StatusCode, body: Bytes, replayed: bool) -> Response {
+    let mut response = Response::new(Body::from(body));
+    *response.status_mut() = status;
+    let headers = response.headers_mut();
+    headers.insert(header::CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    if replayed {
+        headers.insert(REPLAYED_HEADER, HeaderValue::from_static("true"));
+    }
+
+    response
+}
+
+// Answers a repeated upload with the response of the first one instead of storing the files
+// again. Keys are scoped to the tenant and the upload route, failed uploads aren't remembered.
+pub async fn replay_uploads(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Result<Response, ApiError> {
+    let Some(value) = request.headers().get(HEADER) else {
+        return Ok(next.run(request).await);
+    };
+
+    let key = value.to_str().ok()
+        .map(str::trim)
+        .filter(|key| !key.is_empty() && key.len() <= MAX_KEY_LEN)
+        .ok_or_else(|| ApiError::BadRequest(format!("Invalid {} header", HEADER)))?;
+    let tenant = request.extensions().get::<Tenant>().cloned().unwrap_or_default();
+    let key = Key {
+        tenant: tenant.0,
+        method: request.method().clone(),
+        path: request.uri().path().to_string(),
+        key: key.to_string(),
+    };
+
+    let cache = &state.idempotency;
+    match cache.begin(&key) {
+        Lookup::Done(status, body) => return Ok(json_response(status, body, true)),
+        Lookup::InProgress => {
+            return Err(ApiError::Conflict(format!("an upload with this {} is still in progress", HEADER)));
+        }
+        Lookup::New => {}
+    }
+
+    let mut claim = Claim { cache, key: &key, done: false };
+    let response = next.run(request).await;
+    if !response.status().is_success() {
+        return Ok(response);
+    }
+
+    let (parts, body) = response.into_parts();
+    let body = axum::body::to_bytes(body, MAX_CACHED_BODY).await
+        .map_err(|e| ApiError::InternalError(format!("Failed to read upload response: {}", e)))?;
+    cache.finish(&key, parts.status, body.clone());
+    claim.done = true;
+
+    Ok(Response::from_parts(parts, Body::from(body)))
+}
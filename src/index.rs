@@ -0,0 +1,292 @@
+use std::{collections::HashMap, fs, io::{self, Read}, path::Path, sync::Mutex};
+
+use chrono::{DateTime, Utc};
+use rusqlite::{Connection, params};
+use serde_json::{Value, json};
+use sha2::{Digest, Sha256};
+
+use crate::files;
+
+// What the index knows about a stored file, `path` is relative to the data dir
+pub struct Record {
+    pub path: String,
+    pub size: u64,
+    pub sha256: Option<String>,
+    pub content_type: Option<String>,
+    // Unix seconds
+    pub uploaded_at: i64,
+    // The name the file was uploaded under, before sanitizing and timestamping
+    pub original_name: Option<String>,
+}
+
+impl Record {
+    fn from_row(row: &rusqlite::Row) -> rusqlite::Result<Self> {
+        Ok(Self {
+            path: row.get(0)?,
+            size: row.get::<_, i64>(1)? as u64,
+            sha256: row.get(2)?,
+            content_type: row.get(3)?,
+            uploaded_at: row.get(4)?,
+            original_name: row.get(5)?,
+        })
+    }
+
+    fn relative_to(self, prefix: &str) -> Self {
+        let path = self.path.strip_prefix(prefix).unwrap_or(&self.path).to_string();
+        Self { path, ..self }
+    }
+
+    // Same shape as `StoredFile::describe`, plus what only the index knows
+    pub fn describe(&self) -> Value {
+        let modified = DateTime::<Utc>::from_timestamp(self.uploaded_at, 0).map(|t| t.to_rfc3339());
+
+        json!({
+            "path": self.path,
+            "size": self.size,
+            "modified": modified,
+            "sha256": self.sha256,
+            "content_type": self.content_type
+        })
+    }
+}
+
+// SQLite table mirroring the data dir, so listings don't have to walk the tree
+pub struct Index {
+    conn: Mutex<Connection>,
+}
+
+impl Index {
+    pub fn open(path: &Path) -> rusqlite::Result<Self> {
+        let conn = Connection::open(path)?;
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS files (
+                path TEXT PRIMARY KEY,
+                size INTEGER NOT NULL,
+                sha256 TEXT,
+                content_type TEXT,
+                uploaded_at INTEGER NOT NULL,
+                original_name TEXT
+            )",
+        )?;
+
+        // Indexes created before original names were recorded lack the column
+        let columns: Vec<String> = conn.prepare("SELECT name FROM pragma_table_info('files')")?
+            .query_map([], |row| row.get(0))?
+            .collect::<rusqlite::Result<_>>()?;
+        if !columns.iter().any(|column| column == "original_name") {
+            conn.execute_batch("ALTER TABLE files ADD COLUMN original_name TEXT")?;
+        }
+
+        Ok(Self { conn: Mutex::new(conn) })
+    }
+
+    fn conn(&self) -> std::sync::MutexGuard<'_, Connection> {
+        self.conn.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn insert(&self, record: &Record) -> rusqlite::Result<()> {
+        self.conn().execute(
+            "INSERT OR REPLACE INTO files (path, size, sha256, content_type, uploaded_at, original_name) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![record.path, record.size as i64, record.sha256, record.content_type, record.uploaded_at, record.original_name],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn remove(&self, path: &str) -> rusqlite::Result<()> {
+        self.conn().execute("DELETE FROM files WHERE path = ?1", params![path])?;
+
+        Ok(())
+    }
+
+    // Drops every file below a folder, e.g. a deleted day
+    pub fn remove_dir(&self, dir: &str) -> rusqlite::Result<usize> {
+        self.conn().execute("DELETE FROM files WHERE substr(path, 1, length(?1)) = ?1", params![format!("{}/", dir)])
+    }
+
+    pub fn list(&self) -> rusqlite::Result<Vec<Record>> {
+        let conn = self.conn();
+        let mut statement = conn.prepare("SELECT path, size, sha256, content_type, uploaded_at, original_name FROM files ORDER BY path")?;
+        let records = statement.query_map([], Record::from_row)?;
+
+        records.collect()
+    }
+
+    // One page of the files below `prefix` in path order, along with their total number.
+    // Returned paths are relative to the prefix.
+    pub fn page(&self, prefix: &str, limit: usize, offset: usize) -> rusqlite::Result<(Vec<Record>, usize)> {
+        let conn = self.conn();
+        let total: i64 = conn.query_row(
+            "SELECT COUNT(*) FROM files WHERE substr(path, 1, length(?1)) = ?1",
+            params![prefix],
+            |row| row.get(0),
+        )?;
+
+        let mut statement = conn.prepare(
+            "SELECT path, size, sha256, content_type, uploaded_at, original_name FROM files
+             WHERE substr(path, 1, length(?1)) = ?1 ORDER BY path LIMIT ?2 OFFSET ?3",
+        )?;
+        let records = statement.query_map(params![prefix, limit as i64, offset as i64], Record::from_row)?;
+
+        let records = records
+            .map(|record| record.map(|record| record.relative_to(prefix)))
+            .collect::<rusqlite::Result<_>>()?;
+        Ok((records, total as usize))
+    }
+
+    // Every file below `prefix` that `accept` takes, in path order. The filter sees and the
+    // result holds paths relative to the prefix.
+    pub fn filtered(&self, prefix: &str, accept: impl Fn(&Record) -> bool) -> rusqlite::Result<Vec<Record>> {
+        Ok(self.list()?
+            .into_iter()
+            .filter(|record| record.path.starts_with(prefix))
+            .map(|record| record.relative_to(prefix))
+            .filter(|record| accept(record))
+            .collect())
+    }
+
+    // Case-insensitive substring match on the file name, without the day folder, or on the
+    // original name
+    pub fn search(&self, prefix: &str, query: &str) -> rusqlite::Result<Vec<Record>> {
+        let needle = query.to_lowercase();
+
+        Ok(self.list()?
+            .into_iter()
+            .filter(|record| record.path.starts_with(prefix))
+            .filter(|record| {
+                file_name(&record.path).to_lowercase().contains(&needle)
+                    || record.original_name.as_ref().is_some_and(|name| name.to_lowercase().contains(&needle))
+            })
+            .map(|record| record.relative_to(prefix))
+            .collect())
+    }
+
+    // Brings the index in line with what's actually on disk. Rows without a file are dropped,
+    // new or resized files are hashed and added. Returns how many rows were added and removed.
+    pub fn reconcile(&self, data_dir: &Path) -> rusqlite::Result<(usize, usize)> {
+        let mut known: HashMap<String, (u64, bool)> = self.list()?
+            .into_iter()
+            .map(|record| (record.path, (record.size, record.original_name.is_some())))
+            .collect();
+
+        let mut added = 0;
+        for (entry, relative) in files::walk(data_dir) {
+            let Ok(metadata) = entry.metadata() else {
+                continue;
+            };
+
+            if let Some((size, named)) = known.remove(&relative)
+                && size == metadata.len()
+            {
+                // Rows indexed before original names were pick them up from the upload metadata
+                if !named && let Some(name) = original_name(data_dir, &relative) {
+                    self.conn().execute("UPDATE files SET original_name = ?1 WHERE path = ?2", params![name, relative])?;
+                }
+                continue;
+            }
+
+            let uploaded_at = metadata.modified()
+                .map(|t| DateTime::<Utc>::from(t).timestamp())
+                .unwrap_or_default();
+            let sha256 = match hash_file(entry.path()) {
+                Ok(sha256) => Some(sha256),
+                Err(e) => {
+                    tracing::warn!(path = %entry.path().display(), error = %e, "Failed to hash file for the index");
+                    None
+                }
+            };
+
+            self.insert(&Record {
+                content_type: mime_guess::from_path(entry.path()).first().map(|mime| mime.to_string()),
+                original_name: original_name(data_dir, &relative),
+                path: relative,
+                size: metadata.len(),
+                sha256,
+                uploaded_at,
+            })?;
+            added += 1;
+        }
+
+        for path in known.keys() {
+            self.remove(path)?;
+        }
+
+        Ok((added, known.len()))
+    }
+}
+
+// Recorded in the metadata sidecar at upload time, files stored before that have none
+fn original_name(data_dir: &Path, relative: &str) -> Option<String> {
+    let meta: Value = serde_json::from_slice(&fs::read(data_dir.join(files::meta_key(relative))).ok()?).ok()?;
+    meta["original_name"].as_str().map(str::to_string)
+}
+
+fn file_name(path: &str) -> &str {
+    path.rsplit('/').next().unwrap_or(path)
+}
+
+fn hash_file(path: &Path) -> io::Result<String> {
+    let mut file = fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; 64 * 1024];
+
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+
+    Ok(hex::encode(hasher.finalize()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::TempDir;
+
+    fn record(path: &str, original_name: Option<&str>) -> Record {
+        Record {
+            path: path.to_string(),
+            size: 4,
+            sha256: None,
+            content_type: None,
+            uploaded_at: 0,
+            original_name: original_name.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn search_matches_stored_and_original_names() {
+        let dir = TempDir::new();
+        let index = Index::open(&dir.path().join("index.db")).unwrap();
+        index.insert(&record("2024-01-01/1704067200_app.log", Some("nightly/app.log"))).unwrap();
+        index.insert(&record("2024-01-01/1704067201_db.log", None)).unwrap();
+
+        let paths = |query| index.search("", query).unwrap().into_iter().map(|record| record.path).collect::<Vec<_>>();
+        assert_eq!(paths("NIGHTLY"), ["2024-01-01/1704067200_app.log"]);
+        assert_eq!(paths("db"), ["2024-01-01/1704067201_db.log"]);
+        assert!(paths("weekly").is_empty());
+    }
+
+    #[test]
+    fn older_indexes_gain_original_names_from_the_metadata() {
+        let dir = TempDir::new();
+        let data_dir = dir.path().join("data");
+        fs::create_dir_all(data_dir.join("2024-01-01")).unwrap();
+        fs::write(data_dir.join("2024-01-01/1704067200_app.log"), "boot").unwrap();
+        fs::write(data_dir.join(files::meta_key("2024-01-01/1704067200_app.log")), r#"{"original_name":"nightly/app.log"}"#).unwrap();
+
+        let path = dir.path().join("index.db");
+        Connection::open(&path).unwrap().execute_batch(
+            "CREATE TABLE files (path TEXT PRIMARY KEY, size INTEGER NOT NULL, sha256 TEXT, content_type TEXT, uploaded_at INTEGER NOT NULL);
+             INSERT INTO files VALUES ('2024-01-01/1704067200_app.log', 4, NULL, NULL, 0);",
+        ).unwrap();
+
+        let index = Index::open(&path).unwrap();
+        assert!(index.search("", "nightly").unwrap().is_empty());
+        assert_eq!(index.reconcile(&data_dir).unwrap(), (0, 0));
+        assert_eq!(index.search("", "nightly").unwrap().len(), 1);
+    }
+}
@@ -1,163 +1,244 @@
-use std::fs;
+mod archive;
+mod audit;
+mod auth;
+mod client;
+mod config;
+mod decompress;
+mod download;
+mod files;
+mod health;
+mod idempotency;
+#[cfg(feature = "sqlite")]
+mod index;
+mod metrics;
+mod openapi;
+mod rate_limit;
+mod request_id;
+mod resumable;
+mod retention;
+mod stats;
+mod storage;
+mod tenant;
+mod upload;
+mod verify;
+mod webhook;
 
-use axum::{Json, Router, extract::Request, http::StatusCode, response::IntoResponse, routing::{get, post}};
-use axum_extra::extract::Multipart;
+#[cfg(test)]
+mod tests;
+
+use std::{collections::HashMap, convert::Infallible, fs, net::SocketAddr, path::{Path, PathBuf}, sync::{Arc, RwLock, RwLockReadGuard, atomic::{AtomicU64, Ordering}}, time::{Duration, Instant}};
+
+use axum::{Json, Router, body::Body, extract::{DefaultBodyLimit, Request, State}, http::{Extensions, HeaderMap, StatusCode, Version, header}, middleware::{self, Next}, response::{IntoResponse, Response}, routing::{get, patch, post, put}};
+use ipnet::IpNet;
 use serde_json::json;
-use zip::{ZipWriter, unstable::LittleEndianWriteExt, write::FileOptions};
+use tower::limit::GlobalConcurrencyLimitLayer;
+use tower_http::{
+    compression::{CompressionLayer, DefaultPredicate, Predicate, predicate::NotForContentType},
+    classify::{ServerErrorsAsFailures, SharedClassifier},
+    trace::{DefaultOnBodyChunk, DefaultOnEos, MakeSpan, OnResponse, TraceLayer},
+};
+use tracing::Span;
+use utoipa::OpenApi;
+use utoipa_swagger_ui::SwaggerUi;
+
+use tracing_appender::{non_blocking::WorkerGuard, rolling::RollingFileAppender};
+use tracing_subscriber::EnvFilter;
+
+use crate::{audit::AuditLog, auth::ApiKey, config::{AppConfig, StorageConfig}, idempotency::IdempotencyCache, openapi::ApiDoc, storage::{LocalFs, Prefixed, S3, StorageBackend}, tenant::Tenant, metrics::Metrics, rate_limit::RateLimiter, resumable::ResumableUploads, stats::StatsCache, webhook::Webhook};
+
+struct AppState {
+    config: AppConfig,
+    storage: Arc<dyn StorageBackend>,
+    // Running total of bytes stored in the data dir, so quota checks don't walk the tree
+    used_bytes: AtomicU64,
+    upload_limiter: Option<RateLimiter>,
+    idempotency: IdempotencyCache,
+    resumable: ResumableUploads,
+    metrics: Metrics,
+    webhook: Option<Webhook>,
+    audit: Option<AuditLog>,
+    // Reloaded from the config file on SIGHUP
+    api_keys: RwLock<Vec<ApiKey>>,
+    stats: StatsCache,
+    // For the uptime reported by /health
+    started: Instant,
+    #[cfg(feature = "sqlite")]
+    index: Option<index::Index>,
+}
+
+impl AppState {
+    fn new(config: AppConfig) -> Self {
+        let (_, used_bytes) = files::usage(&config.data_dir);
+
+        let upload_limiter = config.upload_rate_limit.map(RateLimiter::new);
+        let idempotency = IdempotencyCache::new(config.idempotency_ttl);
+        let resumable = ResumableUploads::new(config.resumable_upload_ttl);
+        let storage: Arc<dyn StorageBackend> = match &config.storage {
+            StorageConfig::Local => Arc::new(LocalFs::new(config.data_dir.clone())),
+            StorageConfig::S3 { endpoint, bucket, region, access_key, secret_key } => Arc::new(
+                S3::new(endpoint, bucket, region, access_key, secret_key).expect("Failed to configure S3 storage"),
+            ),
+        };
+        let webhook = config.webhook_url.clone().map(|url| {
+            Webhook::new(url, config.webhook_timeout).expect("Failed to create webhook client")
+        });
+
+        let audit = config.audit_log.as_ref().map(|path| {
+            AuditLog::open(path, &config.data_dir, config.audit_strict).expect("Failed to open audit log")
+        });
+        let api_keys = RwLock::new(config.api_keys.clone());
+
+        // Pick up files that were added or removed while the server wasn't running
+        #[cfg(feature = "sqlite")]
+        let index = config.index_path.as_ref().map(|path| {
+            let index = index::Index::open(path).expect("Failed to open index");
+            let (added, removed) = index.reconcile(&config.data_dir).expect("Failed to reconcile index");
+            tracing::info!(path = %path.display(), added, removed, "Index reconciled");
+            index
+        });
+
+        Self {
+            config,
+            storage,
+            used_bytes: AtomicU64::new(used_bytes),
+            upload_limiter,
+            idempotency,
+            resumable,
+            metrics: Metrics::new().expect("Failed to register metrics"),
+            webhook,
+            audit,
+            api_keys,
+            stats: StatsCache::default(),
+            started: Instant::now(),
+            #[cfg(feature = "sqlite")]
+            index,
+        }
+    }
+
+    // Storage as seen by a tenant
+    fn storage_for(&self, tenant: &Tenant) -> Arc<dyn StorageBackend> {
+        match &tenant.0 {
+            Some(_) => Arc::new(Prefixed::new(self.storage.clone(), tenant.prefix())),
+            None => self.storage.clone(),
+        }
+    }
+
+    // Local folder holding a tenant's files
+    fn data_dir_for(&self, tenant: &Tenant) -> PathBuf {
+        match &tenant.0 {
+            Some(id) => self.config.data_dir.join(id),
+            None => self.config.data_dir.clone(),
+        }
+    }
+
+    // Folder the retention sweep packs a tenant's old days into, if it archives them at all
+    fn archive_dir_for(&self, tenant: &Tenant) -> Option<PathBuf> {
+        let archive_dir = self.config.retention_archive_dir.as_ref()?;
+        match &tenant.0 {
+            Some(id) => Some(archive_dir.join(id)),
+            None => Some(archive_dir.clone()),
+        }
+    }
+
+    fn api_keys(&self) -> RwLockReadGuard<'_, Vec<ApiKey>> {
+        self.api_keys.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn used_bytes(&self) -> u64 {
+        self.used_bytes.load(Ordering::Relaxed)
+    }
+
+    fn add_used_bytes(&self, bytes: u64) {
+        self.used_bytes.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    // Counts `bytes` as used only if that stays within `max`, in one step so that concurrent
+    // uploads can't both squeeze into the last free bytes
+    fn try_add_used_bytes(&self, bytes: u64, max: u64) -> bool {
+        self.used_bytes
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| used.checked_add(bytes).filter(|total| *total <= max))
+            .is_ok()
+    }
+
+    fn release_used_bytes(&self, bytes: u64) {
+        let _ = self.used_bytes.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| {
+            Some(used.saturating_sub(bytes))
+        });
+    }
+}
 
 enum ApiError {
+    Unauthorized,
+    Forbidden,
     NotFound,
+    Conflict(String),
+    PayloadTooLarge(usize),
+    InsufficientStorage,
+    StorageFull,
+    TooManyRequests(u64),
+    RangeNotSatisfiable(u64),
+    NotAcceptable(String),
+    RequestTimeout(u64),
+    UnsupportedMediaType(String),
     BadRequest(String),
     InternalError(String)
 }
 
 impl IntoResponse for ApiError {
     fn into_response(self) -> axum::response::Response {
-        let (status, error_message) = match self {
-            ApiError::NotFound => (StatusCode::NOT_FOUND, "No resources could be found.".to_string()),
-            ApiError::BadRequest(msg) => (StatusCode::BAD_REQUEST, format!("There is something wrong with your request: {}", msg)),
-            ApiError::InternalError(msg) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Something went wrong. Probably not your fault: {}", msg)),
+        let extra_header = match &self {
+            ApiError::TooManyRequests(seconds) => Some((header::RETRY_AFTER, seconds.to_string())),
+            ApiError::RangeNotSatisfiable(len) => Some((header::CONTENT_RANGE, format!("bytes */{}", len))),
+            _ => None,
+        };
+
+        let (status, code, error_message) = match self {
+            ApiError::Unauthorized => (StatusCode::UNAUTHORIZED, "unauthorized", "A valid bearer token is required.".to_string()),
+            ApiError::Forbidden => (StatusCode::FORBIDDEN, "forbidden", "You are not allowed to access this resource.".to_string()),
+            ApiError::NotFound => (StatusCode::NOT_FOUND, "not_found", "No resources could be found.".to_string()),
+            ApiError::Conflict(msg) => (StatusCode::CONFLICT, "conflict", format!("The resource already exists: {}", msg)),
+            ApiError::PayloadTooLarge(limit) => (StatusCode::PAYLOAD_TOO_LARGE, "too_large", format!("Uploads are limited to {} bytes.", limit)),
+            ApiError::InsufficientStorage => (StatusCode::INSUFFICIENT_STORAGE, "quota_exceeded", "The storage quota has been reached.".to_string()),
+            ApiError::StorageFull => (StatusCode::INSUFFICIENT_STORAGE, "disk_full", "The server ran out of disk space, try again later.".to_string()),
+            ApiError::TooManyRequests(seconds) => (StatusCode::TOO_MANY_REQUESTS, "rate_limited", format!("Too many uploads, try again in {} seconds.", seconds)),
+            ApiError::RangeNotSatisfiable(len) => (StatusCode::RANGE_NOT_SATISFIABLE, "range_not_satisfiable", format!("The requested range is outside of the file's {} bytes.", len)),
+            ApiError::NotAcceptable(msg) => (StatusCode::NOT_ACCEPTABLE, "not_acceptable", format!("None of the accepted types can be served: {}", msg)),
+            ApiError::RequestTimeout(seconds) => (StatusCode::REQUEST_TIMEOUT, "timeout", format!("Requests have to be answered within {} seconds.", seconds)),
+            ApiError::UnsupportedMediaType(msg) => (StatusCode::UNSUPPORTED_MEDIA_TYPE, "unsupported_media_type", format!("The request body has an unsupported type: {}", msg)),
+            ApiError::BadRequest(msg) => (StatusCode::BAD_REQUEST, "bad_request", format!("There is something wrong with your request: {}", msg)),
+            ApiError::InternalError(msg) => (StatusCode::INTERNAL_SERVER_ERROR, "internal_error", format!("Something went wrong. Probably not your fault: {}", msg)),
         };
 
         let body = Json(json!({
-            "error": error_message
+            "error": error_message,
+            "code": code,
+            "request_id": request_id::current()
         }));
 
-        (status, body).into_response()
-    }
-}
-
-async fn health_check() -> impl IntoResponse {
-    Json(json!({
-        "status": "ok",
-        "message": "Server is running :)"
-    }))
-}
+        if status.is_server_error() {
+            tracing::error!(status = status.as_u16(), code, error = %error_message, "Request failed");
+        } else {
+            tracing::warn!(status = status.as_u16(), code, error = %error_message, "Request rejected");
+        }
 
-async fn download_log() -> Result<impl IntoResponse, ApiError> {
-    use std::io::Write;
-    use axum::response::Response;
-    use axum::body::Body;
-    use axum::http::header;
-    
-    let data_dir = "/opt/eotw_data";
-    
-    if !std::path::Path::new(data_dir).exists() {
-        return Err(ApiError::NotFound);
-    }
-    let mut zip_buffer = Vec::new();
-    {
-        let mut zip = ZipWriter::new(std::io::Cursor::new(&mut zip_buffer));
-        let options = FileOptions::<()>::default()
-            .compression_method(zip::CompressionMethod::Deflated)
-            .unix_permissions(0o755);
-        
-        for entry in walkdir::WalkDir::new(data_dir)
-            .into_iter()
-            .filter_map(|e| e.ok())
-            .filter(|e| e.file_type().is_file())
+        let mut response = (status, body).into_response();
+        if let Some((name, value)) = extra_header
+            && let Ok(value) = value.parse()
         {
-            let path = entry.path();
-            let name = path.strip_prefix(data_dir)
-                .map_err(|e| ApiError::InternalError(format!("Path error: {}", e)))?;
-            
-            let file_data = fs::read(path)
-                .map_err(|e| ApiError::InternalError(format!("Failed to read file: {}", e)))?;
-            
-            zip.start_file(name.to_string_lossy().to_string(), options)
-                .map_err(|e| ApiError::InternalError(format!("Failed to add file to zip: {}", e)))?;
-            
-            zip.write(&file_data)
-                .map_err(|e| ApiError::InternalError(format!("Failed to write to zip: {}", e)))?;
+            response.headers_mut().insert(name, value);
         }
-        
-        zip.finish()
-            .map_err(|e| ApiError::InternalError(format!("Failed to finalize zip: {}", e)))?;
-    }
-    
-    let timestamp = chrono::Local::now().format("%Y%m%d_%H%M%S");
-    let filename = format!("logs_{}.zip", timestamp);
-    
-    let response = Response::builder()
-        .status(StatusCode::OK)
-        .header(header::CONTENT_TYPE, "application/zip")
-        .header(
-            header::CONTENT_DISPOSITION,
-            format!("attachment; filename=\"{}\"", filename)
-        )
-        .body(Body::from(zip_buffer))
-        .map_err(|e| ApiError::InternalError(format!("Failed to build response: {}", e)))?;
-    
-    Ok(response)
-}
 
-async fn upload_log(mut multipart: Multipart) -> Result<impl IntoResponse, ApiError> {
-    let mut file_saved = false;
-
-    // Create subfolder for each day
-    let now = chrono::Local::now();
-    let date_dir = now.format("%Y-%m-%d").to_string();
-    let upload_dir = format!("/opt/eotw_data/{}", date_dir);
-    fs::create_dir_all(&upload_dir)
-        .map_err(|e| ApiError::InternalError(format!("Failed to create directory: {}", e)))?;
-    
-    // Iterate through file
-    while let Some(field) = multipart.next_field().await
-        .map_err(|e| ApiError::BadRequest(format!("Failed to read multipart field: {}", e)))? 
-    {
-        let name = field.name()
-            .ok_or_else(|| ApiError::BadRequest("Field name is missing".to_string()))?
-            .to_string();
-        
-        let file_name = field.file_name()
-            .ok_or_else(|| ApiError::BadRequest("File name is missing".to_string()))?
-            .to_string();
-        
-        let data = field.bytes().await
-            .map_err(|e| ApiError::BadRequest(format!("Failed to read file data: {}", e)))?;
-        
-        let timestamp = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap()
-            .as_secs();
-        let safe_file_name = format!("{}_{}", timestamp, file_name.replace(['/', '\\'], "_"));
-        let file_path = format!("{}/{}", upload_dir, safe_file_name);
-        
-        fs::write(&file_path, &data)
-            .map_err(|e| ApiError::InternalError(format!("Failed to save file: {}", e)))?;
-        file_saved = true;
-        println!("File uploaded: {} -> {}", file_name, file_path);
+        response
     }
-    
-    if !file_saved {
-        return Err(ApiError::BadRequest("No file was uploaded".to_string()));
-    }
-
-    Ok(Json(json!({
-        "status": "success",
-        "message": "File uploaded successfully"
-    })))
 }
 
-async fn next_model() -> Result<impl IntoResponse, ApiError> {
-    use std::collections::HashMap;
-    
-    let data_dir = "/opt/eotw_data";
-    
-    if !std::path::Path::new(data_dir).exists() {
-        return Err(ApiError::NotFound);
-    }
-    
+// Counts which language model each stored log was recorded with, based on its first
+// System/Languagemodel line
+fn count_models(data_dir: &Path) -> HashMap<String, usize> {
     let mut model_counts: HashMap<String, usize> = HashMap::new();
     
-    for entry in walkdir::WalkDir::new(data_dir)
-        .into_iter()
-        .filter_map(|e| e.ok())
-        .filter(|e| e.file_type().is_file())
-    {
-        let path = entry.path();
-        
-        if let Ok(content) = fs::read_to_string(path) {
+    for (entry, _) in files::walk(data_dir) {
+        if let Ok(content) = fs::read_to_string(entry.path()) {
             for line in content.lines().skip(1) { // Skip header
                 let fields: Vec<&str> = line.split('\t').collect();
                 
@@ -174,6 +255,29 @@ async fn next_model() -> Result<impl IntoResponse, ApiError> {
             }
         }
     }
+
+    model_counts
+}
+
+#[utoipa::path(
+    get,
+    path = "/nextmodel",
+    description = "The language model with the fewest recorded logs",
+    responses(
+        (status = 200, body = crate::openapi::NextModel),
+        (status = 404, description = "No logs recorded yet", body = crate::openapi::ErrorBody),
+    ),
+)]
+async fn next_model(State(state): State<Arc<AppState>>) -> Result<impl IntoResponse, ApiError> {
+    let data_dir = state.config.data_dir.clone();
+    
+    if tokio::fs::metadata(&data_dir).await.is_err() {
+        return Err(ApiError::NotFound);
+    }
+    
+    let model_counts = tokio::task::spawn_blocking(move || count_models(&data_dir))
+        .await
+        .map_err(|e| ApiError::InternalError(format!("Failed to count models: {}", e)))?;
     
     if model_counts.is_empty() {
         return Err(ApiError::NotFound);
@@ -190,29 +294,253 @@ async fn next_model() -> Result<impl IntoResponse, ApiError> {
     })))
 }
 
-fn create_app() -> Router {
+// Resolves once the process is asked to stop, either by Ctrl+C or SIGTERM
+async fn shutdown_signal() {
+    use tokio::signal;
+
+    let ctrl_c = async {
+        signal::ctrl_c().await.expect("Failed to listen for Ctrl+C");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        signal::unix::signal(signal::unix::SignalKind::terminate())
+            .expect("Failed to listen for SIGTERM")
+            .recv()
+            .await;
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+
+    tracing::info!("Shutting down, waiting for in-flight requests...");
+}
+
+// Wraps each request in a span carrying the request id, so every line logged while handling it
+// can be matched to the id in error bodies, and logs one event once the response is ready.
+// Streamed bodies may still be sending at that point. Only the path is recorded, queries may
+// hold archive passwords. Failures are left to ApiError, which logs them already.
+fn trace(trusted_proxies: Vec<IpNet>) -> TraceLayer<
+    SharedClassifier<ServerErrorsAsFailures>,
+    impl MakeSpan<Body> + Clone,
+    (),
+    impl OnResponse<Body> + Clone,
+    DefaultOnBodyChunk,
+    DefaultOnEos,
+    (),
+> {
+    TraceLayer::new_for_http()
+        .make_span_with(move |request: &axum::extract::Request| {
+            let client = client::ClientIp(client::client_ip(request.headers(), request.extensions(), &trusted_proxies));
+            tracing::info_span!(
+                "request",
+                method = %request.method(),
+                path = request.uri().path(),
+                client = %client,
+                request_id = request_id::current().unwrap_or_default(),
+            )
+        })
+        .on_request(())
+        .on_response(|response: &axum::response::Response, latency: Duration, _: &Span| {
+            tracing::info!(status = response.status().as_u16(), duration_ms = latency.as_millis() as u64, "Request finished");
+        })
+        .on_failure(())
+}
+
+// Answers requests whose response isn't ready within --request-timeout with a 408. The handler
+// is dropped halfway, which also removes the temp file of an unfinished upload.
+async fn time_out(State(state): State<Arc<AppState>>, request: Request, next: Next) -> Result<Response, ApiError> {
+    let timeout = state.config.request_timeout;
+    tokio::time::timeout(timeout, next.run(request)).await
+        .map_err(|_| ApiError::RequestTimeout(timeout.as_secs()))
+}
+
+// Gzip or brotli as the client accepts. Archives are passed through as they are, zip and gzip
+// are compressed already and a plain tar was asked for uncompressed. Ranged responses have to
+// stay byte-exact, so those are passed through too.
+fn compression() -> CompressionLayer<impl Predicate> {
+    let predicate = DefaultPredicate::new()
+        .and(NotForContentType::const_new("application/zip"))
+        .and(NotForContentType::const_new("application/x-tar"))
+        .and(NotForContentType::const_new("application/gzip"))
+        .and(|status: StatusCode, _: Version, _: &HeaderMap, _: &Extensions| status != StatusCode::PARTIAL_CONTENT);
+
+    CompressionLayer::new().compress_when(predicate)
+}
+
+fn create_app(state: Arc<AppState>) -> Router {
+    // Both upload routes share one budget
+    let upload_limit = GlobalConcurrencyLimitLayer::new(state.config.max_concurrent_uploads);
+
+    let protected = Router::new()
+        .route(
+            "/upload",
+            post(upload::upload_log)
+                .layer::<_, Infallible>(upload_limit.clone())
+                .layer(DefaultBodyLimit::max(state.config.max_upload_size))
+                .layer(middleware::from_fn_with_state(state.clone(), idempotency::replay_uploads))
+                .layer(middleware::from_fn_with_state(state.clone(), decompress::gunzip_uploads))
+                .layer(middleware::from_fn_with_state(state.clone(), upload::check_content_length))
+                .layer(middleware::from_fn_with_state(state.clone(), rate_limit::limit_uploads)),
+        )
+        .route(
+            "/upload/{filename}",
+            put(upload::put_log)
+                .layer::<_, Infallible>(upload_limit.clone())
+                .layer(DefaultBodyLimit::max(state.config.max_upload_size))
+                .layer(middleware::from_fn_with_state(state.clone(), idempotency::replay_uploads))
+                .layer(middleware::from_fn_with_state(state.clone(), decompress::gunzip_uploads))
+                .layer(middleware::from_fn_with_state(state.clone(), upload::check_content_length))
+                .layer(middleware::from_fn_with_state(state.clone(), rate_limit::limit_uploads)),
+        )
+        .route(
+            "/upload/init",
+            post(resumable::init)
+                .layer(middleware::from_fn_with_state(state.clone(), rate_limit::limit_uploads)),
+        )
+        // Shares the path with the PUT route, which is why the upload id goes by filename here
+        .route(
+            "/upload/{filename}",
+            patch(resumable::append)
+                .layer::<_, Infallible>(upload_limit.clone())
+                .layer(DefaultBodyLimit::max(state.config.max_upload_size))
+                .layer(middleware::from_fn_with_state(state.clone(), upload::check_content_length))
+                .get(resumable::status),
+        )
+        .route("/upload/{filename}/complete", post(resumable::complete))
+        .route("/download", get(download::download_log).head(download::download_log).post(download::download_selection).delete(download::delete_range))
+        .route("/download/manifest", get(download::download_manifest))
+        .route("/download/{date}", get(download::download_day).delete(download::delete_day))
+        .route("/download/{date}/verify", get(verify::verify))
+        .route("/files", get(files::list_files).layer(middleware::from_fn_with_state(state.clone(), files::cache_listings)))
+        .route("/files.csv", get(files::list_files_csv).layer(middleware::from_fn_with_state(state.clone(), files::cache_listings)))
+        .route("/search", get(files::search).layer(middleware::from_fn_with_state(state.clone(), files::cache_listings)))
+        .route("/dates", get(files::list_dates).layer(middleware::from_fn_with_state(state.clone(), files::cache_listings)))
+        .route("/stats", get(stats::stats).layer(middleware::from_fn_with_state(state.clone(), files::cache_listings)))
+        .route("/admin/cleanup", post(retention::cleanup))
+        .route("/files/{*path}", get(files::download_file).delete(files::delete_file))
+        .route_layer(middleware::from_fn_with_state(state.clone(), time_out))
+        .route_layer(middleware::from_fn_with_state(state.clone(), auth::require_token))
+        .route_layer(GlobalConcurrencyLimitLayer::new(state.config.max_concurrent_requests));
+
     Router::new()
-        .route("/health", get(health_check))
-        .route("/upload", post(upload_log))
-        .route("/download", get(download_log))
+        .route("/health", get(health::livez))
+        .route("/livez", get(health::livez))
+        .route("/readyz", get(health::readyz))
+        .route("/version", get(health::version))
         .route("/nextmodel", get(next_model))
+        .route("/metrics", get(metrics::metrics))
+        .merge(SwaggerUi::new("/docs").url("/openapi.json", ApiDoc::openapi()))
+        .merge(protected)
+        .layer(compression())
+        .layer(trace(state.config.trusted_proxies.clone()))
+        .layer(middleware::from_fn(request_id::assign))
+        .with_state(state)
+}
+
+// Exits with a readable error instead of panicking later when the data dir can't be created or
+// written to, which mostly means a volume mounted with the wrong owner
+fn ensure_data_dir(data_dir: &Path) {
+    let Err(e) = fs::create_dir_all(data_dir).and_then(|_| health::probe_writable(data_dir)) else {
+        return;
+    };
+
+    let path = std::path::absolute(data_dir).unwrap_or_else(|_| data_dir.to_path_buf());
+    tracing::error!(
+        path = %path.display(),
+        error = %e,
+        "Data directory is not usable. Make sure it exists or can be created, and that the user running the server may write to it, e.g. by fixing the owner of a mounted volume"
+    );
+    std::process::exit(1);
+}
+
+// Logs to the console unless a log file is configured. The returned guard flushes the file's
+// buffered lines when dropped, so it has to live as long as the server.
+fn init_tracing(config: &AppConfig) -> Option<WorkerGuard> {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+
+    let Some(path) = &config.log_file else {
+        tracing_subscriber::fmt().with_env_filter(filter).init();
+        return None;
+    };
+
+    let directory = path.parent().filter(|p| !p.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let file_name = Path::new(path.file_name().unwrap_or_default());
+    let mut builder = RollingFileAppender::builder()
+        .rotation(config.log_rotation.into())
+        .filename_prefix(file_name.file_stem().unwrap_or_default().to_string_lossy());
+    if let Some(extension) = file_name.extension() {
+        builder = builder.filename_suffix(extension.to_string_lossy());
+    }
+    if let Some(max_files) = config.log_max_files {
+        builder = builder.max_log_files(max_files);
+    }
+
+    // The appender would create the directory too, but not before complaining that it can't
+    // look for old files to prune in it
+    let appender = fs::create_dir_all(directory)
+        .map_err(|e| e.to_string())
+        .and_then(|_| builder.build(directory).map_err(|e| e.to_string()))
+        .unwrap_or_else(|e| {
+            eprintln!("Failed to open log file {}: {}", path.display(), e);
+            std::process::exit(1);
+        });
+    let (writer, guard) = tracing_appender::non_blocking(appender);
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_ansi(false)
+        .with_writer(writer)
+        .init();
+
+    Some(guard)
 }
 
 #[tokio::main]
 async fn main() {
+    let config = config::load();
+    let _log_guard = init_tracing(&config);
+    if let Err(errors) = config.validate() {
+        for error in &errors {
+            tracing::error!("Invalid configuration: {}", error);
+        }
+        std::process::exit(1);
+    }
+
     // Setup directory for data
-    fs::create_dir_all("/opt/eotw_data").unwrap();
+    ensure_data_dir(&config.data_dir);
+
+    let state = Arc::new(AppState::new(config));
+
+    #[cfg(unix)]
+    if let Some(path) = state.config.config_path.clone() {
+        tokio::spawn(auth::reload_keys_on_hangup(state.clone(), path));
+    }
+
+    // Abandoned resumable uploads would otherwise pile up in the data dir
+    tokio::spawn(resumable::expire(state.clone()));
+
+    // Periodically delete old logs
+    if let Some(retention_days) = state.config.retention_days {
+        tokio::spawn(retention::run(state.clone(), retention_days));
+    }
 
     // Serve app
-    let app = create_app();
+    let bind = state.config.bind;
+    let app = create_app(state);
 
-    let listener = tokio::net::TcpListener::bind("0.0.0.0:3000")
+    let listener = tokio::net::TcpListener::bind(bind)
         .await
         .expect("Failed to bind TCP Listener!");
 
-    println!("Server running...");
+    tracing::info!(%bind, "Server running...");
 
-    axum::serve(listener, app)
+    axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>())
+        .with_graceful_shutdown(shutdown_signal())
         .await
         .expect("failed to start server :(");
 }
@@ -0,0 +1,114 @@
+use std::{
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use axum::{
+    body::Bytes,
+    extract::State,
+    http::header,
+    response::IntoResponse,
+};
+use futures_util::Stream;
+use prometheus::{Encoder, Histogram, HistogramOpts, IntCounter, IntGauge, Registry, TextEncoder};
+
+use crate::{ApiError, AppState};
+
+pub struct Metrics {
+    registry: Registry,
+    pub uploads: IntCounter,
+    pub downloads: IntCounter,
+    pub upload_size: Histogram,
+    pub download_size: Histogram,
+    data_dir_bytes: IntGauge,
+}
+
+// Powers of four from 1 KiB to 4 GiB, log files range from a few lines to multi-day dumps and
+// archives of whole months
+fn size_buckets() -> prometheus::Result<Vec<f64>> {
+    prometheus::exponential_buckets(1024.0, 4.0, 12)
+}
+
+impl Metrics {
+    pub fn new() -> prometheus::Result<Self> {
+        let registry = Registry::new();
+
+        let uploads = IntCounter::new("eotw_uploads_total", "Number of files uploaded")?;
+        let downloads = IntCounter::new("eotw_downloads_total", "Number of downloads served")?;
+        let upload_size = Histogram::with_opts(
+            HistogramOpts::new("eotw_upload_size_bytes", "Size of uploaded files in bytes")
+                .buckets(size_buckets()?),
+        )?;
+        let download_size = Histogram::with_opts(
+            HistogramOpts::new("eotw_download_size_bytes", "Size of served files and archives in bytes")
+                .buckets(size_buckets()?),
+        )?;
+        let data_dir_bytes = IntGauge::new("eotw_data_dir_bytes", "Total size of the data directory in bytes")?;
+
+        registry.register(Box::new(uploads.clone()))?;
+        registry.register(Box::new(downloads.clone()))?;
+        registry.register(Box::new(upload_size.clone()))?;
+        registry.register(Box::new(download_size.clone()))?;
+        registry.register(Box::new(data_dir_bytes.clone()))?;
+
+        Ok(Self {
+            registry,
+            uploads,
+            downloads,
+            upload_size,
+            download_size,
+            data_dir_bytes,
+        })
+    }
+}
+
+// Passes a streamed response body through and records how much of it was sent once it ends or
+// the client goes away, for archives whose size isn't known up front
+pub struct Measured<S> {
+    inner: S,
+    bytes: u64,
+    histogram: Histogram,
+}
+
+impl<S> Measured<S> {
+    pub fn new(inner: S, histogram: Histogram) -> Self {
+        Self { inner, bytes: 0, histogram }
+    }
+}
+
+impl<S: Stream<Item = std::io::Result<Bytes>> + Unpin> Stream for Measured<S> {
+    type Item = std::io::Result<Bytes>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let poll = Pin::new(&mut self.inner).poll_next(cx);
+        if let Poll::Ready(Some(Ok(chunk))) = &poll {
+            self.bytes += chunk.len() as u64;
+        }
+
+        poll
+    }
+}
+
+impl<S> Drop for Measured<S> {
+    fn drop(&mut self) {
+        self.histogram.observe(self.bytes as f64);
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    responses((status = 200, description = "Prometheus text format", content_type = "text/plain", body = String)),
+)]
+pub async fn metrics(State(state): State<Arc<AppState>>) -> Result<impl IntoResponse, ApiError> {
+    let metrics = &state.metrics;
+    metrics.data_dir_bytes.set(state.used_bytes().try_into().unwrap_or(i64::MAX));
+
+    let encoder = TextEncoder::new();
+    let mut buffer = Vec::new();
+    encoder.encode(&metrics.registry.gather(), &mut buffer)
+        .map_err(|e| ApiError::InternalError(format!("Failed to encode metrics: {}", e)))?;
+
+    Ok(([(header::CONTENT_TYPE, encoder.format_type().to_string())], buffer))
+}
@@ -0,0 +1,280 @@
+// The handlers build their JSON bodies with `json!`, the structs below only describe those bodies
+// for the spec and are never constructed
+#![allow(dead_code)]
+
+use std::collections::HashMap;
+
+use serde::Serialize;
+use utoipa::{
+    Modify, OpenApi, ToSchema,
+    openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme},
+};
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "eotwsink", description = "Sink for uploaded EOTW logs"),
+    paths(
+        crate::health::livez,
+        crate::health::readyz,
+        crate::health::version,
+        crate::next_model,
+        crate::metrics::metrics,
+        crate::upload::upload_log,
+        crate::upload::put_log,
+        crate::resumable::init,
+        crate::resumable::status,
+        crate::resumable::append,
+        crate::resumable::complete,
+        crate::download::download_log,
+        crate::download::download_selection,
+        crate::download::download_manifest,
+        crate::download::download_day,
+        crate::download::delete_day,
+        crate::download::delete_range,
+        crate::verify::verify,
+        crate::files::list_files,
+        crate::files::list_files_csv,
+        crate::files::search,
+        crate::files::list_dates,
+        crate::stats::stats,
+        crate::files::download_file,
+        crate::files::delete_file,
+        crate::retention::cleanup,
+    ),
+    modifiers(&BearerAuth),
+)]
+pub struct ApiDoc;
+
+// Protected routes take the auth token or a tenant token as a bearer token
+struct BearerAuth;
+
+impl Modify for BearerAuth {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "bearer",
+            SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build()),
+        );
+    }
+}
+
+// Body of every error response
+#[derive(Serialize, ToSchema)]
+pub struct ErrorBody {
+    // Meant for humans, the wording may change
+    error: String,
+    code: ErrorCode,
+    request_id: Option<String>,
+}
+
+// Stable identifier of an error for clients to branch on
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    // 401, no, a malformed or an unknown bearer token
+    Unauthorized,
+    // 403, a valid token lacking the route's scope
+    Forbidden,
+    NotFound,
+    // 409, a name taken in the same second or an Idempotency-Key still in use
+    Conflict,
+    // 413
+    TooLarge,
+    // 507, --max-total-bytes reached
+    QuotaExceeded,
+    // 507, the disk itself is full, worth retrying later
+    DiskFull,
+    // 429, see Retry-After
+    RateLimited,
+    RangeNotSatisfiable,
+    // 406, an Accept header ruling out every archive format
+    NotAcceptable,
+    // 408, no response within --request-timeout
+    Timeout,
+    UnsupportedMediaType,
+    BadRequest,
+    InternalError,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct Status {
+    #[schema(example = "ok")]
+    status: String,
+    message: String,
+    // Since the process started, a low value on every check means it keeps restarting
+    uptime_seconds: u64,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct Readiness {
+    #[schema(example = "ok")]
+    status: String,
+    message: String,
+    writable: bool,
+    available_bytes: Option<u64>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct Version {
+    #[schema(example = "0.1.0")]
+    version: String,
+    // Git commit hash, unknown when built outside a checkout
+    commit: String,
+    built_at: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct NextModel {
+    next_model: String,
+    counts: HashMap<String, usize>,
+}
+
+#[derive(ToSchema)]
+pub struct UploadForm {
+    // Any number of file fields, each stored as its own file. The field name is configurable,
+    // file by default
+    #[schema(value_type = String, format = Binary)]
+    file: Vec<u8>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct UploadedFile {
+    original_name: String,
+    #[schema(example = "2024-05-01/1714550400_session.log")]
+    stored_path: String,
+    size: u64,
+    sha256: String,
+    deduplicated: bool,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct Uploaded {
+    #[schema(example = "success")]
+    status: String,
+    message: String,
+    files: Vec<UploadedFile>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ResumableUpload {
+    upload_id: String,
+    file_name: String,
+    // Bytes received so far, the next chunk starts here
+    offset: u64,
+    // Unknown until declared at init or in a Content-Range
+    size: Option<u64>,
+    // A TTL after data last arrived
+    expires_at: String,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct FileEntry {
+    #[schema(example = "2024-05-01/1714550400_session.log")]
+    path: String,
+    size: u64,
+    modified: Option<String>,
+    // Only with meta=true
+    meta: Option<FileMeta>,
+}
+
+// Recorded next to each upload
+#[derive(Serialize, ToSchema)]
+pub struct FileMeta {
+    original_name: String,
+    content_type: Option<String>,
+    size: u64,
+    sha256: String,
+    uploaded_at: Option<String>,
+    uploader_ip: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct FilePage {
+    total: usize,
+    limit: usize,
+    offset: usize,
+    files: Vec<FileEntry>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct Manifest {
+    files: usize,
+    total_bytes: u64,
+    entries: Vec<FileEntry>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct DayUsage {
+    #[schema(example = "2024-05-01")]
+    date: String,
+    // Unknown for archives packed without a record of their contents
+    files: Option<usize>,
+    total_bytes: u64,
+    // Packed into a tarball by the retention sweep, downloads serve that tarball as is
+    archived: bool,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct Dates {
+    dates: Vec<DayUsage>,
+    // Top level folders that aren't named after a day
+    other: Vec<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct Stats {
+    files: usize,
+    total_bytes: u64,
+    // Day folders holding at least one file
+    days: usize,
+    oldest_upload: Option<String>,
+    newest_upload: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct DeletedDay {
+    #[schema(example = "success")]
+    status: String,
+    date: String,
+    deleted_files: usize,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct DeletedRange {
+    #[schema(example = "success")]
+    status: String,
+    deleted_folders: usize,
+    deleted_files: usize,
+    // Days that had a folder, those without one don't count
+    dates: Vec<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct Verification {
+    #[schema(example = "2024-05-01")]
+    date: String,
+    // Checked as the tarball the retention sweep packed the day into
+    archived: bool,
+    files: usize,
+    // Uncompressed, as read back from the archive
+    total_bytes: u64,
+    ok: bool,
+    failures: Vec<VerificationFailure>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct VerificationFailure {
+    // Stored path of the entry, or the date when the archive as a whole is broken
+    #[schema(example = "2024-05-01/1714521600_app.log")]
+    path: String,
+    error: String,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct Cleanup {
+    #[schema(example = "success")]
+    status: String,
+    // Removed day folders, below their tenant's folder where there is one
+    #[schema(example = json!(["2024-05-01", "acme/2024-05-02"]))]
+    removed: Vec<String>,
+}
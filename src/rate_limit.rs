@@ -0,0 +1,94 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::Response,
+};
+
+use crate::{ApiError, AppState, client};
+
+// Upper bound on tracked clients so a flood of distinct addresses can't grow the map forever
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+
+struct Bucket {
+    tokens: f64,
+    last_seen: Instant,
+}
+
+// Token bucket per client IP, refilling `per_minute` tokens every minute
+pub struct RateLimiter {
+    per_minute: u32,
+    buckets: Mutex<HashMap<IpAddr, Bucket>>,
+}
+
+impl RateLimiter {
+    pub fn new(per_minute: u32) -> Self {
+        Self {
+            per_minute,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn refill_rate(&self) -> f64 {
+        f64::from(self.per_minute) / 60.0
+    }
+
+    // Takes a token for `ip`, or returns the seconds until the next one is available
+    pub fn check(&self, ip: IpAddr) -> Result<(), u64> {
+        let now = Instant::now();
+        let capacity = f64::from(self.per_minute);
+        let rate = self.refill_rate();
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+
+        if buckets.len() >= MAX_TRACKED_CLIENTS && !buckets.contains_key(&ip) {
+            evict(&mut buckets, now, capacity, rate);
+        }
+
+        let bucket = buckets.entry(ip).or_insert(Bucket { tokens: capacity, last_seen: now });
+        let elapsed = now.duration_since(bucket.last_seen).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * rate).min(capacity);
+        bucket.last_seen = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(((1.0 - bucket.tokens) / rate).ceil() as u64)
+        }
+    }
+}
+
+// Drops clients whose bucket has refilled completely, they are indistinguishable from new
+// ones. If everyone is still active the least recently seen client goes.
+fn evict(buckets: &mut HashMap<IpAddr, Bucket>, now: Instant, capacity: f64, rate: f64) {
+    buckets.retain(|_, bucket| {
+        let elapsed = now.duration_since(bucket.last_seen).as_secs_f64();
+        bucket.tokens + elapsed * rate < capacity
+    });
+
+    if buckets.len() >= MAX_TRACKED_CLIENTS
+        && let Some(oldest) = buckets.iter().min_by_key(|(_, b)| b.last_seen).map(|(ip, _)| *ip)
+    {
+        buckets.remove(&oldest);
+    }
+}
+
+pub async fn limit_uploads(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Result<Response, ApiError> {
+    if let Some(limiter) = &state.upload_limiter
+        && let Some(ip) = client::client_ip(request.headers(), request.extensions(), &state.config.trusted_proxies)
+    {
+        limiter.check(ip).map_err(ApiError::TooManyRequests)?;
+    }
+
+    Ok(next.run(request).await)
+}
@@ -0,0 +1,533 @@
+use std::{
+    collections::HashSet,
+    io::Read,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+use axum::{
+    Json,
+    body::Body,
+    extract::{Path as UrlPath, State},
+    http::{HeaderMap, StatusCode, header},
+    response::IntoResponse,
+};
+use chrono::{DateTime, Utc};
+use futures_util::StreamExt;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use sha2::{Digest, Sha256};
+use tokio::io::AsyncWriteExt;
+use utoipa::ToSchema;
+
+use crate::{ApiError, AppState, client::ClientIp, tenant::Tenant, upload::{self, Assembled, Destination, Reservation}};
+
+// Partial uploads are kept in a hidden folder of the data dir, so listings and retention skip
+// them and completing one is a rename on the same filesystem
+const PARTIAL_DIR: &str = ".partial";
+
+// How often abandoned uploads are looked for
+const SWEEP_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+// What's known about an upload besides its data, stored next to it
+#[derive(Serialize, Deserialize)]
+struct Info {
+    tenant: Option<String>,
+    file_name: String,
+    content_type: Option<String>,
+    // Declared at init or by the first Content-Range with a total
+    size: Option<u64>,
+}
+
+struct Upload {
+    id: String,
+    info: Info,
+    // Bytes received so far, the next chunk has to start here
+    offset: u64,
+    // When data last arrived, the upload expires a TTL after that
+    touched: SystemTime,
+}
+
+impl Upload {
+    fn describe(&self, ttl: Duration) -> Value {
+        let expires_at = DateTime::<Utc>::from(self.touched + ttl);
+
+        json!({
+            "upload_id": self.id,
+            "file_name": self.info.file_name,
+            "offset": self.offset,
+            "size": self.info.size,
+            "expires_at": expires_at.to_rfc3339()
+        })
+    }
+}
+
+// Partial uploads some request is working on right now. Appending to the same upload from two
+// requests at once would interleave their data.
+pub struct ResumableUploads {
+    ttl: Duration,
+    busy: Mutex<HashSet<String>>,
+}
+
+impl ResumableUploads {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            busy: Mutex::new(HashSet::new()),
+        }
+    }
+
+    fn busy(&self) -> std::sync::MutexGuard<'_, HashSet<String>> {
+        self.busy.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn claim<'a>(&'a self, id: &'a str) -> Result<Claim<'a>, ApiError> {
+        if !self.busy().insert(id.to_string()) {
+            return Err(ApiError::Conflict(format!("upload {} is busy with another request", id)));
+        }
+
+        Ok(Claim { uploads: self, id })
+    }
+}
+
+// Releases a claimed upload when the request ends, however it ends
+struct Claim<'a> {
+    uploads: &'a ResumableUploads,
+    id: &'a str,
+}
+
+impl Drop for Claim<'_> {
+    fn drop(&mut self) {
+        self.uploads.busy().remove(self.id);
+    }
+}
+
+fn partial_dir(data_dir: &Path) -> PathBuf {
+    data_dir.join(PARTIAL_DIR)
+}
+
+// Where an upload's data and info live. IDs are generated here, anything else is never a path.
+fn paths(state: &AppState, id: &str) -> Option<(PathBuf, PathBuf)> {
+    let canonical = uuid::Uuid::try_parse(id).ok()?.simple().to_string();
+    if canonical != id {
+        return None;
+    }
+
+    let dir = partial_dir(&state.config.data_dir);
+    Some((dir.join(id), dir.join(format!("{}.json", id))))
+}
+
+fn expired(touched: SystemTime, ttl: Duration) -> bool {
+    touched.elapsed().is_ok_and(|idle| idle > ttl)
+}
+
+// Uploads of other tenants and expired ones don't exist, as far as the client is concerned
+async fn load(state: &AppState, tenant: &Tenant, id: &str) -> Result<Upload, ApiError> {
+    let (data_path, info_path) = paths(state, id).ok_or(ApiError::NotFound)?;
+    let info: Info = tokio::fs::read(&info_path).await.ok()
+        .and_then(|contents| serde_json::from_slice(&contents).ok())
+        .ok_or(ApiError::NotFound)?;
+    if info.tenant != tenant.0 {
+        return Err(ApiError::NotFound);
+    }
+
+    let metadata = tokio::fs::metadata(&data_path).await
+        .map_err(|_| ApiError::NotFound)?;
+    let touched = metadata.modified().unwrap_or_else(|_| SystemTime::now());
+    if expired(touched, state.resumable.ttl) {
+        return Err(ApiError::NotFound);
+    }
+
+    Ok(Upload {
+        id: id.to_string(),
+        info,
+        offset: metadata.len(),
+        touched,
+    })
+}
+
+async fn save_info(state: &AppState, id: &str, info: &Info) -> Result<(), ApiError> {
+    let (_, info_path) = paths(state, id).ok_or(ApiError::NotFound)?;
+    let contents = serde_json::to_vec(info)
+        .map_err(|e| ApiError::InternalError(format!("Failed to encode upload info: {}", e)))?;
+
+    tokio::fs::write(info_path, contents).await
+        .map_err(upload::save_error)
+}
+
+fn check_size(state: &AppState, size: u64) -> Result<(), ApiError> {
+    let limit = state.config.max_resumable_upload_size;
+    if size > limit {
+        return Err(ApiError::PayloadTooLarge(limit as usize));
+    }
+
+    Ok(())
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct InitRequest {
+    /// Name the file is stored under, after the timestamp of its completion
+    filename: String,
+    content_type: Option<String>,
+    /// Total size in bytes, if known up front
+    size: Option<u64>,
+}
+
+// Starts an upload that's sent in several requests, so a dropped connection only loses the
+// chunk in flight
+#[utoipa::path(
+    post,
+    path = "/upload/init",
+    request_body = InitRequest,
+    responses(
+        (status = 201, body = crate::openapi::ResumableUpload),
+        (status = 400, description = "Invalid file name or empty file", body = crate::openapi::ErrorBody),
+        (status = 401, description = "Missing or invalid bearer token", body = crate::openapi::ErrorBody),
+        (status = 413, description = "Declared size over the resumable upload limit", body = crate::openapi::ErrorBody),
+        (status = 429, description = "Upload rate limit exceeded", body = crate::openapi::ErrorBody),
+        (status = 507, description = "Storage quota reached or disk full", body = crate::openapi::ErrorBody),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn init(
+    State(state): State<Arc<AppState>>,
+    tenant: Tenant,
+    Json(request): Json<InitRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    if upload::sanitize_filename(&request.filename).is_none() {
+        return Err(ApiError::BadRequest(format!("Invalid file name: {}", request.filename)));
+    }
+
+    if let Some(size) = request.size {
+        if size == 0 {
+            return Err(ApiError::BadRequest("empty file".to_string()));
+        }
+        check_size(&state, size)?;
+
+        if let Some(max_total_bytes) = state.config.max_total_bytes
+            && state.used_bytes() + size > max_total_bytes
+        {
+            return Err(ApiError::InsufficientStorage);
+        }
+    }
+
+    let id = uuid::Uuid::new_v4().simple().to_string();
+    let Some((data_path, _)) = paths(&state, &id) else {
+        return Err(ApiError::InternalError("Generated an invalid upload id".to_string()));
+    };
+    tokio::fs::create_dir_all(partial_dir(&state.config.data_dir)).await
+        .map_err(upload::save_error)?;
+
+    let info = Info {
+        tenant: tenant.0.clone(),
+        file_name: request.filename,
+        content_type: request.content_type,
+        size: request.size,
+    };
+    save_info(&state, &id, &info).await?;
+    tokio::fs::File::create(&data_path).await
+        .map_err(upload::save_error)?;
+
+    tracing::info!(upload = %id, file = %info.file_name, size = ?info.size, "Resumable upload started");
+
+    let upload = Upload { id, info, offset: 0, touched: SystemTime::now() };
+    Ok((StatusCode::CREATED, Json(upload.describe(state.resumable.ttl))))
+}
+
+// How far an upload got, to pick up where a dropped connection left off
+#[utoipa::path(
+    get,
+    path = "/upload/{id}",
+    params(("id" = String, Path, description = "As returned by /upload/init")),
+    responses(
+        (status = 200, body = crate::openapi::ResumableUpload),
+        (status = 401, description = "Missing or invalid bearer token", body = crate::openapi::ErrorBody),
+        (status = 404, description = "Unknown or expired upload", body = crate::openapi::ErrorBody),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn status(
+    State(state): State<Arc<AppState>>,
+    tenant: Tenant,
+    UrlPath(id): UrlPath<String>,
+) -> Result<impl IntoResponse, ApiError> {
+    let upload = load(&state, &tenant, &id).await?;
+
+    Ok(Json(upload.describe(state.resumable.ttl)))
+}
+
+// Parses `bytes START-END/TOTAL` into inclusive offsets, the total may be *
+fn parse_content_range(value: &str) -> Option<(u64, u64, Option<u64>)> {
+    let (range, total) = value.trim().strip_prefix("bytes ")?.split_once('/')?;
+    let (start, end) = range.split_once('-')?;
+    let start: u64 = start.trim().parse().ok()?;
+    let end: u64 = end.trim().parse().ok()?;
+    let total = match total.trim() {
+        "*" => None,
+        total => Some(total.parse().ok()?),
+    };
+
+    (start <= end && total.is_none_or(|total| end < total)).then_some((start, end, total))
+}
+
+// Appends the body at the offset given by Content-Range, which has to be where the upload
+// currently ends. Whatever arrived of a chunk stays when its request breaks off.
+#[utoipa::path(
+    patch,
+    path = "/upload/{id}",
+    params(
+        ("id" = String, Path, description = "As returned by /upload/init"),
+        ("Content-Range" = String, Header, description = "Position of the body in the file, like bytes 0-1048575/52428800. The total may be *"),
+    ),
+    request_body(content = Vec<u8>, content_type = "application/octet-stream"),
+    responses(
+        (status = 200, body = crate::openapi::ResumableUpload),
+        (status = 400, description = "Missing or invalid Content-Range, or a body of a different length", body = crate::openapi::ErrorBody),
+        (status = 401, description = "Missing or invalid bearer token", body = crate::openapi::ErrorBody),
+        (status = 404, description = "Unknown or expired upload", body = crate::openapi::ErrorBody),
+        (status = 409, description = "The range doesn't start at the current offset, or another request is appending", body = crate::openapi::ErrorBody),
+        (status = 413, description = "Chunk or file over the upload limits", body = crate::openapi::ErrorBody),
+        (status = 507, description = "Storage quota reached or disk full", body = crate::openapi::ErrorBody),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn append(
+    State(state): State<Arc<AppState>>,
+    tenant: Tenant,
+    UrlPath(id): UrlPath<String>,
+    headers: HeaderMap,
+    body: Body,
+) -> Result<impl IntoResponse, ApiError> {
+    let (start, end, total) = headers.get(header::CONTENT_RANGE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(parse_content_range)
+        .ok_or_else(|| ApiError::BadRequest("Missing or invalid Content-Range, expected bytes START-END/TOTAL".to_string()))?;
+    // The raw body escapes the request body limit, so chunks are held to it here
+    let expected = end - start + 1;
+    if expected > state.config.max_upload_size as u64 {
+        return Err(ApiError::PayloadTooLarge(state.config.max_upload_size));
+    }
+
+    let _claim = state.resumable.claim(&id)?;
+    let mut upload = load(&state, &tenant, &id).await?;
+    if start != upload.offset {
+        return Err(ApiError::Conflict(format!("upload {} is at offset {}", id, upload.offset)));
+    }
+
+    if let Some(total) = total {
+        match upload.info.size {
+            Some(size) if size != total => {
+                return Err(ApiError::BadRequest(format!("The upload was declared with {} bytes, not {}", size, total)));
+            }
+            Some(_) => {}
+            None => {
+                check_size(&state, total)?;
+                upload.info.size = Some(total);
+                save_info(&state, &id, &upload.info).await?;
+            }
+        }
+    }
+    if upload.info.size.is_some_and(|size| end >= size) {
+        return Err(ApiError::BadRequest("Content-Range goes past the end of the file".to_string()));
+    }
+    check_size(&state, end + 1)?;
+
+    let Some((data_path, _)) = paths(&state, &id) else {
+        return Err(ApiError::NotFound);
+    };
+    let file = tokio::fs::OpenOptions::new().append(true).open(&data_path).await
+        .map_err(upload::save_error)?;
+    let mut writer = tokio::io::BufWriter::new(file);
+
+    let mut written = 0;
+    let mut too_long = false;
+    let mut chunks = body.into_data_stream();
+    let result = loop {
+        let data = match chunks.next().await {
+            Some(Ok(data)) => data,
+            Some(Err(e)) => break Err(ApiError::BadRequest(format!("Failed to read request body: {}", e))),
+            None => break Ok(()),
+        };
+
+        if written + data.len() as u64 > expected {
+            too_long = true;
+            break Err(ApiError::BadRequest("The body is longer than its Content-Range".to_string()));
+        }
+
+        // Only turns the upload away early, completing it reserves its bytes for good
+        if let Some(max_total_bytes) = state.config.max_total_bytes
+            && state.used_bytes() + start + written + data.len() as u64 > max_total_bytes
+        {
+            break Err(ApiError::InsufficientStorage);
+        }
+
+        if let Err(e) = writer.write_all(&data).await {
+            break Err(upload::save_error(e));
+        }
+        written += data.len() as u64;
+    };
+
+    // Keep what made it, the client resumes from there. A chunk that doesn't match its range
+    // can't be trusted at all though.
+    writer.flush().await
+        .map_err(upload::save_error)?;
+    let file = writer.into_inner();
+    if too_long {
+        file.set_len(start).await
+            .map_err(upload::save_error)?;
+        written = 0;
+    }
+    file.sync_all().await
+        .map_err(upload::save_error)?;
+    result?;
+
+    upload.offset = start + written;
+    upload.touched = SystemTime::now();
+    if written < expected {
+        return Err(ApiError::BadRequest(format!("The body ended early, the upload is at offset {}", upload.offset)));
+    }
+
+    Ok(Json(upload.describe(state.resumable.ttl)))
+}
+
+fn hash_file(path: &Path) -> std::io::Result<String> {
+    let mut file = std::fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; 64 * 1024];
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+
+    Ok(hex::encode(hasher.finalize()))
+}
+
+// Moves the assembled file into today's folder like any other upload. If that fails the upload
+// stays around, so completing it can be retried.
+#[utoipa::path(
+    post,
+    path = "/upload/{id}/complete",
+    params(("id" = String, Path, description = "As returned by /upload/init")),
+    responses(
+        (status = 200, body = crate::openapi::Uploaded),
+        (status = 400, description = "The upload is empty or still missing bytes", body = crate::openapi::ErrorBody),
+        (status = 401, description = "Missing or invalid bearer token", body = crate::openapi::ErrorBody),
+        (status = 404, description = "Unknown or expired upload", body = crate::openapi::ErrorBody),
+        (status = 409, description = "A file with the same name was stored in the same second, or another request is appending", body = crate::openapi::ErrorBody),
+        (status = 507, description = "Storage quota reached or disk full", body = crate::openapi::ErrorBody),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn complete(
+    State(state): State<Arc<AppState>>,
+    client: ClientIp,
+    tenant: Tenant,
+    UrlPath(id): UrlPath<String>,
+) -> Result<impl IntoResponse, ApiError> {
+    let _claim = state.resumable.claim(&id)?;
+    let upload = load(&state, &tenant, &id).await?;
+    if upload.offset == 0 {
+        return Err(ApiError::BadRequest("empty file".to_string()));
+    }
+    if let Some(size) = upload.info.size
+        && upload.offset != size
+    {
+        return Err(ApiError::BadRequest(format!("Only {} of {} bytes were uploaded", upload.offset, size)));
+    }
+
+    let Some((data_path, info_path)) = paths(&state, &id) else {
+        return Err(ApiError::NotFound);
+    };
+    let sha256 = {
+        let data_path = data_path.clone();
+        tokio::task::spawn_blocking(move || hash_file(&data_path))
+            .await
+            .map_err(|e| ApiError::InternalError(format!("Failed to hash upload: {}", e)))?
+            .map_err(|e| ApiError::InternalError(format!("Failed to hash upload: {}", e)))?
+    };
+
+    // Partial uploads aren't counted, so the quota is checked for the whole file at once. The
+    // reservation is handed back after the commit counted the file.
+    let mut reservation = Reservation::new(&state);
+    reservation.grow(upload.offset)?;
+
+    let destination = Destination::today(&state, &tenant, &client).await?;
+    let (stored_name, timestamp) = destination.stored_name(&upload.info.file_name).await?;
+    let saved = destination.commit(Assembled {
+        file_name: upload.info.file_name,
+        content_type: upload.info.content_type,
+        stored_name,
+        timestamp,
+        temp_path: data_path,
+        sha256,
+        size: upload.offset,
+    })
+    .await?;
+    drop(reservation);
+
+    if let Err(e) = tokio::fs::remove_file(&info_path).await {
+        tracing::warn!(upload = %id, error = %e, "Failed to remove upload info");
+    }
+
+    upload::uploaded(&destination, vec![saved]).await
+}
+
+// Periodically deletes uploads that received no data for longer than the TTL
+pub async fn expire(state: Arc<AppState>) {
+    let mut interval = tokio::time::interval(SWEEP_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        let state = state.clone();
+        let swept = tokio::task::spawn_blocking(move || sweep(&state));
+        match swept.await {
+            Ok(Ok(0)) => {}
+            Ok(Ok(removed)) => tracing::info!(removed, "Expired resumable uploads removed"),
+            Ok(Err(e)) => tracing::error!(error = %e, "Failed to expire resumable uploads"),
+            Err(e) => tracing::error!(error = %e, "Failed to expire resumable uploads"),
+        }
+    }
+}
+
+// Returns the number of removed uploads
+fn sweep(state: &AppState) -> std::io::Result<usize> {
+    let entries = match std::fs::read_dir(partial_dir(&state.config.data_dir)) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e),
+    };
+
+    let mut removed = 0;
+    for entry in entries {
+        let name = entry?.file_name().to_string_lossy().to_string();
+        let id = name.strip_suffix(".json").unwrap_or(&name);
+        let Some((data_path, info_path)) = paths(state, id) else {
+            continue;
+        };
+
+        // An info without data is left over from a completed upload. Both may be gone already
+        // when the upload's other file came first.
+        let Ok(metadata) = std::fs::metadata(&data_path).or_else(|_| std::fs::metadata(&info_path)) else {
+            continue;
+        };
+        if !expired(metadata.modified()?, state.resumable.ttl) || state.resumable.busy().contains(id) {
+            continue;
+        }
+
+        for path in [&data_path, &info_path] {
+            if let Err(e) = std::fs::remove_file(path)
+                && e.kind() != std::io::ErrorKind::NotFound
+            {
+                return Err(e);
+            }
+        }
+        removed += 1;
+    }
+
+    Ok(removed)
+}
@@ -0,0 +1,330 @@
+use std::{fs, io::{self, BufWriter}, path::{Path, PathBuf}, sync::Arc};
+
+use axum::{Json, extract::State, response::IntoResponse};
+use chrono::{Days, NaiveDate};
+use serde_json::json;
+
+use crate::{ApiError, AppState, archive::{self, ArchiveFormat, ArchiveOptions}, audit, client::ClientIp, config::AppConfig, files::{self, DayRange}, storage::{self, StorageBackend, StoredFile}, tenant::Tenant};
+
+// Everyone whose day folders are swept, the data dir itself first. Tenants come from tenant
+// tokens and API keys alike, and the keys reloaded on SIGHUP are picked up by the next sweep.
+fn swept_tenants(state: &AppState) -> Vec<Tenant> {
+    let mut tenants: Vec<String> = state.config.tenants.iter().map(|(_, tenant)| tenant.clone()).collect();
+    tenants.extend(state.api_keys().iter().filter_map(|key| key.tenant.clone()));
+    tenants.sort();
+    tenants.dedup();
+
+    std::iter::once(Tenant(None)).chain(tenants.into_iter().map(|tenant| Tenant(Some(tenant)))).collect()
+}
+
+// Periodically removes day folders that fell out of the retention window
+pub async fn run(state: Arc<AppState>, retention_days: u32) {
+    let mut interval = tokio::time::interval(state.config.retention_interval);
+
+    loop {
+        interval.tick().await;
+
+        // Tenants keep their day folders one level down, in a folder named after them, and the
+        // same goes for their archives
+        let dirs: Vec<_> = swept_tenants(&state).iter()
+            .map(|tenant| (state.storage_for(tenant), state.archive_dir_for(tenant)))
+            .collect();
+        let today = state.config.now().date_naive();
+        let Some(cutoff) = today.checked_sub_days(Days::new(retention_days.into())) else {
+            continue;
+        };
+
+        let swept = state.clone();
+        let sweep = tokio::task::spawn_blocking(move || {
+            let mut total = (0, 0);
+            for (storage, archive_dir) in &dirs {
+                let (removed, bytes) = purge_before(&swept.config, storage.as_ref(), cutoff, archive_dir.as_deref())?;
+                total.0 += removed;
+                total.1 += bytes;
+            }
+
+            // Day folders emptied by deletes that couldn't remove them, or from before that was done
+            match remove_empty_days(&swept.config.data_dir, today, true) {
+                Ok(emptied) if !emptied.is_empty() => {
+                    tracing::info!(removed = emptied.len(), "Removed empty day folders");
+                }
+                Ok(_) => {}
+                Err(e) => tracing::warn!(error = %e, "Failed to remove empty day folders"),
+            }
+
+            Ok::<_, io::Error>(total)
+        });
+
+        match sweep.await {
+            Ok(Ok((0, _))) => {}
+            Ok(Ok((removed, bytes))) => {
+                state.release_used_bytes(bytes);
+                tracing::info!(removed, bytes, %cutoff, "Retention sweep finished");
+
+                let data_dir = state.config.data_dir.clone();
+                if let Ok(Err(e)) = tokio::task::spawn_blocking(move || storage::prune_blobs(&data_dir)).await {
+                    tracing::warn!(error = %e, "Failed to prune blobs");
+                }
+
+                #[cfg(feature = "sqlite")]
+                if state.index.is_some() {
+                    let state = state.clone();
+                    let reconciled = tokio::task::spawn_blocking(move || match &state.index {
+                        Some(index) => index.reconcile(&state.config.data_dir).map(|_| ()),
+                        None => Ok(()),
+                    });
+                    if let Ok(Err(e)) = reconciled.await {
+                        tracing::error!(error = %e, "Failed to reconcile index after retention sweep");
+                    }
+                }
+            }
+            Ok(Err(e)) => tracing::error!(error = %e, "Retention sweep failed"),
+            Err(e) => tracing::error!(error = %e, "Retention sweep failed"),
+        }
+    }
+}
+
+// Deletes every day dated before `cutoff`, or packs it into `archive_dir` first when there is
+// one. The date comes from the folder name so the result doesn't depend on file timestamps,
+// anything not named like a day is left alone.
+// Returns the number of removed days and the bytes they held.
+fn purge_before(config: &AppConfig, storage: &dyn StorageBackend, cutoff: NaiveDate, archive_dir: Option<&Path>) -> std::io::Result<(usize, u64)> {
+    let mut removed = 0;
+    let mut removed_bytes = 0;
+
+    for (date, stored) in files::stored_days(storage)? {
+        if files::parse_day(&date).is_none_or(|day| day >= cutoff) {
+            continue;
+        }
+
+        if let Some(archive_dir) = archive_dir {
+            match archive_day(config, storage, &date, stored, archive_dir) {
+                Ok((count, bytes)) => {
+                    tracing::info!(%date, files = count, bytes, "Retention archived day folder");
+                    removed += 1;
+                    removed_bytes += bytes;
+                }
+                Err(e) => tracing::error!(%date, error = %e, "Failed to archive day folder"),
+            }
+            continue;
+        }
+
+        match storage.delete_dir(&date) {
+            Ok((_, bytes)) => {
+                tracing::info!(%date, bytes, "Retention deleted day folder");
+                removed += 1;
+                removed_bytes += bytes;
+            }
+            Err(e) => tracing::error!(%date, error = %e, "Failed to delete day folder"),
+        }
+    }
+
+    Ok((removed, removed_bytes))
+}
+
+fn archive_name(date: &str) -> String {
+    format!("{}.tar.gz", date)
+}
+
+// Packs the `stored` files of a day into `archive_dir/DATE.tar.gz` and removes the day once the
+// tarball is safely on disk. The file and byte counts go into a hidden sidecar so /dates can
+// report them without unpacking anything. An existing tarball is never overwritten, the day
+// stays instead.
+fn archive_day(config: &AppConfig, storage: &dyn StorageBackend, date: &str, stored: Vec<StoredFile>, archive_dir: &Path) -> io::Result<(usize, u64)> {
+    fs::create_dir_all(archive_dir)?;
+
+    let name = archive_name(date);
+    let path = archive_dir.join(&name);
+    if fs::symlink_metadata(&path).is_ok() {
+        return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{} already exists", path.display())));
+    }
+
+    let prefix = format!("{}/", date);
+    let mut entries: Vec<_> = stored.into_iter()
+        .filter_map(|file| {
+            let name = file.key.strip_prefix(&prefix)?.to_string();
+            Some((file, name))
+        })
+        .collect();
+    entries.sort_by(|a, b| a.1.cmp(&b.1));
+    let count = entries.len();
+    let bytes = entries.iter().map(|(file, _)| file.size).sum();
+
+    let options = ArchiveOptions {
+        format: ArchiveFormat::TarGz,
+        range: DayRange::default(),
+        since: None,
+        compression_method: config.compression_method,
+        compression_level: config.compression_level,
+        compression_threads: 1,
+        deterministic: false,
+        timezone: config.timezone,
+        file_mode: config.archive_file_mode,
+        password: None,
+        include: None,
+        exclude: None,
+    };
+
+    // Written under a hidden name first so a crash never leaves a truncated tarball behind
+    let temp = archive_dir.join(format!(".{}.tmp", name));
+    let written = fs::File::create(&temp).and_then(|file| {
+        let mut writer = BufWriter::new(file);
+        archive::write_archive(&mut writer, storage, &entries, &options)?;
+        writer.into_inner().map_err(|e| e.into_error())?.sync_all()
+    });
+    if let Err(e) = written.and_then(|_| fs::rename(&temp, &path)) {
+        let _ = fs::remove_file(&temp);
+        return Err(e);
+    }
+
+    let meta = json!({ "files": count, "total_bytes": bytes });
+    if let Err(e) = fs::write(archive_dir.join(files::meta_key(&name)), meta.to_string()) {
+        tracing::warn!(path = %path.display(), error = %e, "Failed to record archive details");
+    }
+
+    storage.delete_dir(date)?;
+    Ok((count, bytes))
+}
+
+// Tarball of an archived day, if the sweep packed that day
+pub fn archived_day(archive_dir: &Path, date: &str) -> Option<PathBuf> {
+    let path = archive_dir.join(archive_name(date));
+    fs::metadata(&path).is_ok_and(|metadata| metadata.is_file()).then_some(path)
+}
+
+// Archived days in `archive_dir` with the file and byte counts recorded when they were packed.
+// Tarballs without a readable sidecar report their own size and no file count.
+pub fn archived_days(archive_dir: &Path) -> Vec<(String, Option<usize>, u64)> {
+    let Ok(entries) = fs::read_dir(archive_dir) else {
+        return Vec::new();
+    };
+
+    entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let name = entry.file_name().to_str()?.to_string();
+            let date = name.strip_suffix(".tar.gz").filter(|date| files::parse_day(date).is_some())?;
+            let metadata = entry.metadata().ok().filter(|metadata| metadata.is_file())?;
+
+            let meta: Option<serde_json::Value> = fs::read(archive_dir.join(files::meta_key(&name))).ok()
+                .and_then(|bytes| serde_json::from_slice(&bytes).ok());
+            let count = meta.as_ref().and_then(|meta| meta["files"].as_u64()).map(|count| count as usize);
+            let bytes = meta.as_ref().and_then(|meta| meta["total_bytes"].as_u64()).unwrap_or(metadata.len());
+            Some((date.to_string(), count, bytes))
+        })
+        .collect()
+}
+
+// Removes a folder if there's nothing in it, hidden files included
+pub fn remove_if_empty(dir: &Path) -> io::Result<bool> {
+    match fs::remove_dir(dir) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == io::ErrorKind::DirectoryNotEmpty => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+// Removes the empty day folders in `dir` and returns their paths relative to it. With `tenants`
+// the folders of tenants one level down are swept as well. `dir` itself always stays, and so
+// does today's folder since uploads may be about to land in it.
+pub fn remove_empty_days(dir: &Path, today: NaiveDate, tenants: bool) -> io::Result<Vec<String>> {
+    let mut removed = Vec::new();
+
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if !entry.file_type()?.is_dir() {
+            continue;
+        }
+
+        let name = entry.file_name().to_string_lossy().to_string();
+        let Some(day) = files::parse_day(&name) else {
+            if tenants && !name.starts_with('.') {
+                let days = remove_empty_days(&entry.path(), today, false)?;
+                removed.extend(days.into_iter().map(|day| format!("{}/{}", name, day)));
+            }
+            continue;
+        };
+
+        if day != today && remove_if_empty(&entry.path())? {
+            tracing::info!(path = %entry.path().display(), "Removed empty day folder");
+            removed.push(name);
+        }
+    }
+
+    removed.sort();
+    Ok(removed)
+}
+
+// Sweeps the empty day folders of the requesting tenant, or of everyone without a tenant
+#[utoipa::path(
+    post,
+    path = "/admin/cleanup",
+    responses(
+        (status = 200, body = crate::openapi::Cleanup),
+        (status = 401, description = "Missing or invalid bearer token", body = crate::openapi::ErrorBody),
+        (status = 403, description = "The token lacks the admin scope", body = crate::openapi::ErrorBody),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn cleanup(
+    State(state): State<Arc<AppState>>,
+    client: ClientIp,
+    tenant: Tenant,
+) -> Result<impl IntoResponse, ApiError> {
+    let dir = state.data_dir_for(&tenant);
+    let today = state.config.now().date_naive();
+    let tenants = tenant.0.is_none();
+
+    let removed = tokio::task::spawn_blocking(move || match remove_empty_days(&dir, today, tenants) {
+        // A tenant that never uploaded has no folder yet
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        result => result,
+    })
+    .await
+    .map_err(|e| ApiError::InternalError(format!("Failed to remove empty folders: {}", e)))?
+    .map_err(|e| ApiError::InternalError(format!("Failed to remove empty folders: {}", e)))?;
+
+    tracing::info!(removed = removed.len(), %client, "Empty day folders cleaned up");
+    if !removed.is_empty() {
+        audit::record(&state, "cleanup", &client, &tenant, removed.clone())?;
+    }
+
+    Ok(Json(json!({
+        "status": "success",
+        "removed": removed
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{auth::ApiKey, storage::LocalFs, tests::TestApp};
+
+    fn key(key: &str, tenant: Option<&str>) -> ApiKey {
+        ApiKey { label: key.to_string(), key: key.to_string(), scopes: None, tenant: tenant.map(str::to_string) }
+    }
+
+    #[test]
+    fn tenants_of_tokens_and_api_keys_are_swept_once() {
+        let app = TestApp::new(&["--tenant", "acme-token=acme", "--tenant", "globex-token=globex"]);
+        *app.state.api_keys.write().unwrap() = vec![key("a", Some("acme")), key("b", Some("initech")), key("c", None)];
+
+        let tenants: Vec<_> = swept_tenants(&app.state).into_iter().map(|tenant| tenant.0).collect();
+        assert_eq!(tenants, [None, Some("acme".into()), Some("globex".into()), Some("initech".into())]);
+    }
+
+    #[test]
+    fn days_before_the_cutoff_are_purged_through_the_storage() {
+        let app = TestApp::new(&[]);
+        for (key, contents) in [("2024-01-01/old.log", "old"), ("2024-01-02/new.log", "new"), ("notes/2024-01-01.log", "kept")] {
+            let path = app.dir.path().join(key);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+
+        let storage = LocalFs::new(app.dir.path().to_path_buf());
+        let cutoff = NaiveDate::from_ymd_opt(2024, 1, 2).unwrap();
+        assert_eq!(purge_before(&app.state.config, &storage, cutoff, None).unwrap(), (1, 3));
+        assert_eq!(crate::tests::stored_files(app.dir.path()), ["2024-01-02/new.log", "notes/2024-01-01.log"]);
+    }
+}
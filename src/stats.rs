@@ -0,0 +1,132 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use axum::{Json, extract::State, response::IntoResponse};
+use chrono::{DateTime, Utc};
+use serde_json::{Value, json};
+
+use crate::{ApiError, AppState, files, storage, tenant::Tenant};
+
+// Dashboards poll every few seconds, walking the tree that often isn't worth it
+const CACHE_TTL: Duration = Duration::from_secs(10);
+
+// Totals over a tenant's files
+#[derive(Default)]
+struct Totals {
+    files: usize,
+    bytes: u64,
+    days: BTreeSet<String>,
+    oldest: Option<DateTime<Utc>>,
+    newest: Option<DateTime<Utc>>,
+}
+
+impl Totals {
+    fn add(&mut self, key: &str, size: u64, uploaded: Option<DateTime<Utc>>) {
+        self.files += 1;
+        self.bytes += size;
+
+        if let Some((folder, _)) = key.split_once('/')
+            && files::parse_day(folder).is_some()
+        {
+            self.days.insert(folder.to_string());
+        }
+
+        if let Some(uploaded) = uploaded {
+            self.oldest = Some(self.oldest.map_or(uploaded, |oldest| oldest.min(uploaded)));
+            self.newest = Some(self.newest.map_or(uploaded, |newest| newest.max(uploaded)));
+        }
+    }
+
+    fn describe(&self) -> Value {
+        json!({
+            "files": self.files,
+            "total_bytes": self.bytes,
+            "days": self.days.len(),
+            "oldest_upload": self.oldest.map(|t| t.to_rfc3339()),
+            "newest_upload": self.newest.map(|t| t.to_rfc3339())
+        })
+    }
+}
+
+// Recently computed stats by tenant prefix. There's one entry per configured tenant at most.
+#[derive(Default)]
+pub struct StatsCache {
+    entries: Mutex<HashMap<String, (Instant, Value)>>,
+}
+
+impl StatsCache {
+    fn entries(&self) -> std::sync::MutexGuard<'_, HashMap<String, (Instant, Value)>> {
+        self.entries.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn get(&self, prefix: &str) -> Option<Value> {
+        self.entries()
+            .get(prefix)
+            .filter(|(computed, _)| computed.elapsed() < CACHE_TTL)
+            .map(|(_, stats)| stats.clone())
+    }
+
+    fn insert(&self, prefix: String, stats: Value) {
+        self.entries().insert(prefix, (Instant::now(), stats));
+    }
+}
+
+async fn compute(state: &Arc<AppState>, tenant: &Tenant) -> Result<Totals, ApiError> {
+    let mut totals = Totals::default();
+
+    // The index already knows every file, no need to walk the tree
+    #[cfg(feature = "sqlite")]
+    if state.index.is_some() {
+        let prefix = tenant.prefix();
+        let state = state.clone();
+        let records = tokio::task::spawn_blocking(move || {
+            state.index.as_ref().map_or(Ok(Vec::new()), |index| index.list())
+        })
+        .await
+        .map_err(|e| ApiError::InternalError(format!("Failed to compute stats: {}", e)))?
+        .map_err(|e| ApiError::InternalError(format!("Failed to read index: {}", e)))?;
+
+        for record in &records {
+            if let Some(key) = record.path.strip_prefix(&prefix) {
+                totals.add(key, record.size, DateTime::from_timestamp(record.uploaded_at, 0));
+            }
+        }
+        return Ok(totals);
+    }
+
+    let stored = storage::blocking(&state.storage_for(tenant), |storage| storage.list()).await
+        .map_err(|e| storage::api_error("Failed to compute stats", e))?;
+    for file in &stored {
+        totals.add(&file.key, file.size, file.modified.map(DateTime::from));
+    }
+
+    Ok(totals)
+}
+
+// Storage usage in a single call for dashboards, at most a few seconds old
+#[utoipa::path(
+    get,
+    path = "/stats",
+    responses(
+        (status = 200, body = crate::openapi::Stats),
+        (status = 401, description = "Missing or invalid bearer token", body = crate::openapi::ErrorBody),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn stats(
+    State(state): State<Arc<AppState>>,
+    tenant: Tenant,
+) -> Result<impl IntoResponse, ApiError> {
+    let prefix = tenant.prefix();
+    if let Some(stats) = state.stats.get(&prefix) {
+        return Ok(Json(stats));
+    }
+
+    let stats = compute(&state, &tenant).await?.describe();
+    state.stats.insert(prefix, stats.clone());
+
+    Ok(Json(stats))
+}
@@ -0,0 +1,860 @@
+use std::{
+    fs,
+    io::{self, Read},
+    path::{Component, Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use chrono::{DateTime, Utc};
+use rusty_s3::{Bucket, Credentials, S3Action, UrlStyle, actions::{CreateMultipartUpload, ListObjectsV2}};
+use serde_json::{Value, json};
+
+use crate::{ApiError, files};
+
+// A stored file, `key` is its path relative to the storage root, e.g. `2024-01-31/1706659200_a.tsv`
+#[derive(Clone)]
+pub struct StoredFile {
+    pub key: String,
+    pub size: u64,
+    pub modified: Option<SystemTime>,
+    // Unix permission bits, where the storage has any
+    pub mode: Option<u32>,
+}
+
+impl StoredFile {
+    pub fn describe(&self) -> Value {
+        let modified = self.modified.map(|t| DateTime::<Utc>::from(t).to_rfc3339());
+
+        json!({
+            "path": self.key,
+            "size": self.size,
+            "modified": modified
+        })
+    }
+}
+
+#[cfg(unix)]
+fn file_mode(metadata: &fs::Metadata) -> Option<u32> {
+    use std::os::unix::fs::PermissionsExt;
+
+    Some(metadata.permissions().mode() & 0o777)
+}
+
+#[cfg(not(unix))]
+fn file_mode(_metadata: &fs::Metadata) -> Option<u32> {
+    None
+}
+
+// Where uploads end up. Every method blocks, so call them through `blocking`.
+pub trait StorageBackend: Send + Sync {
+    // Moves a completely written local file into storage under `key`. Fails with AlreadyExists
+    // instead of replacing a file stored under it in the meantime.
+    fn save(&self, key: &str, local: &Path) -> io::Result<()>;
+
+    // Like `save`, but references an already stored copy with the same digest instead of
+    // storing the content again. Returns whether that happened.
+    fn save_deduplicated(&self, key: &str, local: &Path, _sha256: &str) -> io::Result<bool> {
+        self.save(key, local).map(|_| false)
+    }
+
+    // Every stored file ordered by key, hidden files excluded
+    fn list(&self) -> io::Result<Vec<StoredFile>>;
+
+    // Size and modification time of a single stored file
+    fn stat(&self, key: &str) -> io::Result<StoredFile>;
+
+    fn exists(&self, key: &str) -> io::Result<bool> {
+        match self.stat(key) {
+            Ok(_) => Ok(true),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn read(&self, key: &str) -> io::Result<Box<dyn Read + Send>>;
+
+    // Like `read`, skipping the first `offset` bytes
+    fn open(&self, key: &str, offset: u64) -> io::Result<Box<dyn Read + Send>> {
+        let mut reader = self.read(key)?;
+        io::copy(&mut reader.by_ref().take(offset), &mut io::sink())?;
+        Ok(reader)
+    }
+
+    // Returns the number of bytes freed
+    fn delete(&self, key: &str) -> io::Result<u64>;
+
+    // Deletes everything stored below the folder `dir`, hidden files included. Returns the
+    // number of files and bytes freed, hidden files not counted.
+    fn delete_dir(&self, dir: &str) -> io::Result<(usize, u64)>;
+}
+
+// Runs a storage call on a blocking thread
+pub async fn blocking<T, F>(storage: &Arc<dyn StorageBackend>, f: F) -> io::Result<T>
+where
+    T: Send + 'static,
+    F: FnOnce(&dyn StorageBackend) -> io::Result<T> + Send + 'static,
+{
+    let storage = storage.clone();
+    tokio::task::spawn_blocking(move || f(storage.as_ref()))
+        .await
+        .map_err(io::Error::other)?
+}
+
+pub fn api_error(context: &str, e: io::Error) -> ApiError {
+    match e.kind() {
+        io::ErrorKind::NotFound => ApiError::NotFound,
+        io::ErrorKind::InvalidInput => ApiError::BadRequest(e.to_string()),
+        _ => ApiError::InternalError(format!("{}: {}", context, e)),
+    }
+}
+
+// Keys are relative paths made of plain segments only
+fn validate_key(key: &str) -> io::Result<&Path> {
+    let path = Path::new(key);
+    if key.is_empty() || path.components().any(|c| !matches!(c, Component::Normal(_))) {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "Invalid file path"));
+    }
+
+    Ok(path)
+}
+
+// Content addressed copies of deduplicated uploads, every stored name is a hardlink to one
+fn blob_dir(data_dir: &Path) -> PathBuf {
+    data_dir.join(".blobs")
+}
+
+// Drops blobs no stored name links to anymore. Returns the number of removed blobs.
+#[cfg(unix)]
+pub fn prune_blobs(data_dir: &Path) -> io::Result<usize> {
+    use std::os::unix::fs::MetadataExt;
+
+    let entries = match fs::read_dir(blob_dir(data_dir)) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e),
+    };
+
+    let mut removed = 0;
+    for entry in entries {
+        let entry = entry?;
+        if entry.metadata()?.nlink() <= 1 {
+            fs::remove_file(entry.path())?;
+            removed += 1;
+        }
+    }
+
+    Ok(removed)
+}
+
+#[cfg(not(unix))]
+pub fn prune_blobs(_data_dir: &Path) -> io::Result<usize> {
+    Ok(0)
+}
+
+// Drops `blob` once no stored name links to it anymore
+#[cfg(unix)]
+fn prune_blob(blob: &Path) -> io::Result<()> {
+    use std::os::unix::fs::MetadataExt;
+
+    if fs::metadata(blob)?.nlink() <= 1 {
+        fs::remove_file(blob)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn prune_blob(_blob: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+// The data dir itself
+pub struct LocalFs {
+    root: PathBuf,
+}
+
+impl LocalFs {
+    pub fn new(root: PathBuf) -> Self {
+        Self { root }
+    }
+
+    // The blob a stored file is a link to, if it's one. Blobs are named after their digest, the
+    // recorded one saves reading the file unless it went stale.
+    #[cfg(unix)]
+    fn linked_blob(&self, key: &str, metadata: &fs::Metadata) -> Option<PathBuf> {
+        use std::os::unix::fs::MetadataExt;
+
+        if metadata.nlink() <= 1 {
+            return None;
+        }
+
+        let blob = |digest: String| {
+            let blob = blob_dir(&self.root).join(&digest);
+            let linked = digest.len() == 64
+                && digest.bytes().all(|b| b.is_ascii_hexdigit())
+                && fs::metadata(&blob).is_ok_and(|b| b.dev() == metadata.dev() && b.ino() == metadata.ino());
+            linked.then_some(blob)
+        };
+        files::recorded_checksum(self, key)
+            .and_then(blob)
+            .or_else(|| blob(files::sha256(self.read(key).ok()?).ok()?))
+    }
+
+    #[cfg(not(unix))]
+    fn linked_blob(&self, _key: &str, _metadata: &fs::Metadata) -> Option<PathBuf> {
+        None
+    }
+
+    // Symlinks could still point outside of the root, so the canonical path is checked too
+    fn path(&self, key: &str) -> io::Result<PathBuf> {
+        let relative = validate_key(key)?;
+        let root = fs::canonicalize(&self.root)?;
+        let path = fs::canonicalize(root.join(relative))?;
+        if !path.starts_with(&root) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Invalid file path"));
+        }
+
+        Ok(path)
+    }
+}
+
+// Linked and unlinked rather than renamed, a rename would replace whatever got stored at `path`
+// since the name was picked
+fn move_new(local: &Path, path: &Path) -> io::Result<()> {
+    fs::hard_link(local, path)?;
+    fs::remove_file(local)
+}
+
+impl StorageBackend for LocalFs {
+    fn save(&self, key: &str, local: &Path) -> io::Result<()> {
+        let path = self.root.join(validate_key(key)?);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        move_new(local, &path)
+    }
+
+    fn save_deduplicated(&self, key: &str, local: &Path, sha256: &str) -> io::Result<bool> {
+        let path = self.root.join(validate_key(key)?);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let blob = blob_dir(&self.root).join(sha256);
+        match fs::hard_link(&blob, &path) {
+            Ok(()) => {
+                fs::remove_file(local)?;
+                return Ok(true);
+            }
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => return Err(e),
+            // No blob with that digest yet
+            Err(_) => {}
+        }
+
+        move_new(local, &path)?;
+
+        // Later uploads can only be deduplicated against this one once the blob exists
+        if let Err(e) = fs::create_dir_all(blob_dir(&self.root)).and_then(|_| fs::hard_link(&path, &blob)) {
+            tracing::warn!(path = %path.display(), error = %e, "Failed to record blob");
+        }
+
+        Ok(false)
+    }
+
+    fn list(&self) -> io::Result<Vec<StoredFile>> {
+        fs::metadata(&self.root)?;
+
+        Ok(files::walk(&self.root)
+            .filter_map(|(entry, key)| {
+                let metadata = entry.metadata().ok()?;
+                Some(StoredFile { key, size: metadata.len(), modified: metadata.modified().ok(), mode: file_mode(&metadata) })
+            })
+            .collect())
+    }
+
+    fn stat(&self, key: &str) -> io::Result<StoredFile> {
+        let metadata = fs::metadata(self.path(key)?)?;
+        if !metadata.is_file() {
+            return Err(io::ErrorKind::NotFound.into());
+        }
+
+        Ok(StoredFile { key: key.to_string(), size: metadata.len(), modified: metadata.modified().ok(), mode: file_mode(&metadata) })
+    }
+
+    fn read(&self, key: &str) -> io::Result<Box<dyn Read + Send>> {
+        Ok(Box::new(fs::File::open(self.path(key)?)?))
+    }
+
+    fn open(&self, key: &str, offset: u64) -> io::Result<Box<dyn Read + Send>> {
+        use std::io::{Seek, SeekFrom};
+
+        let mut file = fs::File::open(self.path(key)?)?;
+        file.seek(SeekFrom::Start(offset))?;
+        Ok(Box::new(file))
+    }
+
+    fn delete(&self, key: &str) -> io::Result<u64> {
+        let path = self.path(key)?;
+        let metadata = fs::metadata(&path)?;
+        if !metadata.is_file() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Only files can be deleted"));
+        }
+
+        let blob = self.linked_blob(key, &metadata);
+        fs::remove_file(path)?;
+        if let Some(blob) = blob
+            && let Err(e) = prune_blob(&blob)
+        {
+            tracing::warn!(path = %blob.display(), error = %e, "Failed to prune blob");
+        }
+
+        Ok(metadata.len())
+    }
+
+    fn delete_dir(&self, dir: &str) -> io::Result<(usize, u64)> {
+        // A linked folder is never followed, it could hold anything
+        if !fs::symlink_metadata(self.root.join(validate_key(dir)?))?.is_dir() {
+            return Err(io::ErrorKind::NotFound.into());
+        }
+
+        // Never wipe the whole data dir, no matter how the path resolved
+        let path = self.path(dir)?;
+        if path == fs::canonicalize(&self.root)? {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Refusing to delete the data directory"));
+        }
+
+        let usage = files::usage(&path);
+        let mut blobs: Vec<_> = files::walk(&path)
+            .filter_map(|(entry, relative)| {
+                self.linked_blob(&format!("{}/{}", dir.trim_end_matches('/'), relative), &entry.metadata().ok()?)
+            })
+            .collect();
+        blobs.sort();
+        blobs.dedup();
+        fs::remove_dir_all(&path)?;
+        for blob in blobs {
+            if let Err(e) = prune_blob(&blob) {
+                tracing::warn!(path = %blob.display(), error = %e, "Failed to prune blob");
+            }
+        }
+
+        Ok(usage)
+    }
+}
+
+// Another backend restricted to the keys below `prefix`, used to keep tenants apart
+pub struct Prefixed {
+    inner: Arc<dyn StorageBackend>,
+    prefix: String,
+}
+
+impl Prefixed {
+    pub fn new(inner: Arc<dyn StorageBackend>, prefix: String) -> Self {
+        Self { inner, prefix }
+    }
+
+    fn key(&self, key: &str) -> io::Result<String> {
+        validate_key(key)?;
+        Ok(format!("{}{}", self.prefix, key))
+    }
+}
+
+impl StorageBackend for Prefixed {
+    fn save(&self, key: &str, local: &Path) -> io::Result<()> {
+        self.inner.save(&self.key(key)?, local)
+    }
+
+    fn save_deduplicated(&self, key: &str, local: &Path, sha256: &str) -> io::Result<bool> {
+        self.inner.save_deduplicated(&self.key(key)?, local, sha256)
+    }
+
+    fn list(&self) -> io::Result<Vec<StoredFile>> {
+        Ok(self.inner.list()?
+            .into_iter()
+            .filter_map(|file| {
+                let key = file.key.strip_prefix(&self.prefix)?.to_string();
+                Some(StoredFile { key, ..file })
+            })
+            .collect())
+    }
+
+    fn stat(&self, key: &str) -> io::Result<StoredFile> {
+        Ok(StoredFile { key: key.to_string(), ..self.inner.stat(&self.key(key)?)? })
+    }
+
+    fn read(&self, key: &str) -> io::Result<Box<dyn Read + Send>> {
+        self.inner.read(&self.key(key)?)
+    }
+
+    fn open(&self, key: &str, offset: u64) -> io::Result<Box<dyn Read + Send>> {
+        self.inner.open(&self.key(key)?, offset)
+    }
+
+    fn delete(&self, key: &str) -> io::Result<u64> {
+        self.inner.delete(&self.key(key)?)
+    }
+
+    fn delete_dir(&self, dir: &str) -> io::Result<(usize, u64)> {
+        self.inner.delete_dir(&self.key(dir)?)
+    }
+}
+
+// Presigned URLs only have to survive a single request
+const SIGNATURE_TTL: Duration = Duration::from_secs(300);
+
+// Files larger than this are uploaded in parts of this size, so no more than one part is held in
+// memory at a time. S3 wants parts of at least 5 MiB.
+const PART_SIZE: u64 = 8 * 1024 * 1024;
+
+// The next part of a file, empty once it's all been read
+fn read_part(file: &mut fs::File) -> io::Result<Vec<u8>> {
+    let mut part = Vec::new();
+    file.take(PART_SIZE).read_to_end(&mut part)?;
+    Ok(part)
+}
+
+// Any S3 compatible object store. Requests are made with the async client and driven from the
+// blocking thread through the runtime handle.
+pub struct S3 {
+    bucket: Bucket,
+    credentials: Credentials,
+    client: reqwest::Client,
+    runtime: tokio::runtime::Handle,
+}
+
+impl S3 {
+    pub fn new(endpoint: &str, bucket: &str, region: &str, access_key: &str, secret_key: &str) -> Result<Self, String> {
+        let endpoint = endpoint.parse().map_err(|e| format!("Invalid S3 endpoint: {}", e))?;
+        let bucket = Bucket::new(endpoint, UrlStyle::Path, bucket.to_string(), region.to_string())
+            .map_err(|e| format!("Invalid S3 bucket: {}", e))?;
+
+        Ok(Self {
+            bucket,
+            credentials: Credentials::new(access_key, secret_key),
+            client: reqwest::Client::new(),
+            runtime: tokio::runtime::Handle::current(),
+        })
+    }
+
+    fn send(&self, request: reqwest::RequestBuilder) -> io::Result<reqwest::Response> {
+        let response = self.runtime.block_on(request.send()).map_err(io::Error::other)?;
+        match response.status() {
+            reqwest::StatusCode::NOT_FOUND => return Err(io::ErrorKind::NotFound.into()),
+            // Conditional writes refused because the key is taken
+            reqwest::StatusCode::PRECONDITION_FAILED => return Err(io::ErrorKind::AlreadyExists.into()),
+            _ => {}
+        }
+
+        response.error_for_status().map_err(io::Error::other)
+    }
+
+    // Uploads a file too large for a single request part by part. Parts already uploaded are
+    // discarded when one fails.
+    fn save_parts(&self, key: &str, file: &mut fs::File, first_part: Vec<u8>) -> io::Result<()> {
+        let url = self.bucket.create_multipart_upload(Some(&self.credentials), key).sign(SIGNATURE_TTL);
+        let response = self.send(self.client.post(url))?;
+        let body = self.runtime.block_on(response.text()).map_err(io::Error::other)?;
+        let upload_id = CreateMultipartUpload::parse_response(&body).map_err(io::Error::other)?.upload_id().to_string();
+
+        let uploaded = self.upload_parts(key, &upload_id, file, first_part);
+        if uploaded.is_err() {
+            let url = self.bucket.abort_multipart_upload(Some(&self.credentials), key, &upload_id).sign(SIGNATURE_TTL);
+            if let Err(e) = self.send(self.client.delete(url)) {
+                tracing::warn!(%key, error = %e, "Failed to abort multipart upload");
+            }
+        }
+
+        uploaded
+    }
+
+    fn upload_parts(&self, key: &str, upload_id: &str, file: &mut fs::File, first_part: Vec<u8>) -> io::Result<()> {
+        let mut etags = Vec::new();
+        let mut part = first_part;
+        while !part.is_empty() {
+            let number = u16::try_from(etags.len() + 1).map_err(|_| io::Error::other("Too many parts"))?;
+            let url = self.bucket.upload_part(Some(&self.credentials), key, number, upload_id).sign(SIGNATURE_TTL);
+            let response = self.send(self.client.put(url).body(part))?;
+            let etag = response.headers()
+                .get(reqwest::header::ETAG)
+                .and_then(|v| v.to_str().ok())
+                .ok_or_else(|| io::Error::other("Part uploaded without an ETag"))?;
+            etags.push(etag.to_string());
+
+            part = read_part(file)?;
+        }
+
+        let mut action = self.bucket.complete_multipart_upload(Some(&self.credentials), key, upload_id, etags.iter().map(String::as_str));
+        action.headers_mut().insert("if-none-match", "*");
+        let url = action.sign(SIGNATURE_TTL);
+        let body = action.body();
+        self.send(self.client.post(url).header(reqwest::header::IF_NONE_MATCH, "*").body(body))?;
+
+        Ok(())
+    }
+
+    // Every object below `prefix`, hidden ones included
+    fn objects(&self, prefix: &str) -> io::Result<Vec<StoredFile>> {
+        let mut stored = Vec::new();
+        let mut continuation_token = None;
+
+        loop {
+            let mut action = self.bucket.list_objects_v2(Some(&self.credentials));
+            if !prefix.is_empty() {
+                action.with_prefix(prefix);
+            }
+            if let Some(token) = &continuation_token {
+                action.with_continuation_token(token);
+            }
+
+            let response = self.send(self.client.get(action.sign(SIGNATURE_TTL)))?;
+            let body = self.runtime.block_on(response.text()).map_err(io::Error::other)?;
+            let page = ListObjectsV2::parse_response(&body).map_err(io::Error::other)?;
+
+            stored.extend(page.contents.into_iter().map(|object| StoredFile {
+                modified: DateTime::parse_from_rfc3339(&object.last_modified).ok().map(SystemTime::from),
+                key: object.key,
+                size: object.size,
+                mode: None,
+            }));
+
+            continuation_token = page.next_continuation_token;
+            if continuation_token.is_none() {
+                break;
+            }
+        }
+
+        Ok(stored)
+    }
+}
+
+fn hidden(key: &str) -> bool {
+    key.split('/').any(|segment| segment.starts_with('.'))
+}
+
+impl StorageBackend for S3 {
+    // Written only if nothing is stored under the key yet, which S3 decides
+    fn save(&self, key: &str, local: &Path) -> io::Result<()> {
+        validate_key(key)?;
+        let mut file = fs::File::open(local)?;
+
+        let part = read_part(&mut file)?;
+        if (part.len() as u64) < PART_SIZE {
+            let mut action = self.bucket.put_object(Some(&self.credentials), key);
+            action.headers_mut().insert("if-none-match", "*");
+            let url = action.sign(SIGNATURE_TTL);
+            self.send(self.client.put(url).header(reqwest::header::IF_NONE_MATCH, "*").body(part))?;
+        } else {
+            self.save_parts(key, &mut file, part)?;
+        }
+
+        fs::remove_file(local)
+    }
+
+    fn list(&self) -> io::Result<Vec<StoredFile>> {
+        let mut stored: Vec<_> = self.objects("")?.into_iter().filter(|file| !hidden(&file.key)).collect();
+        stored.sort_by(|a, b| a.key.cmp(&b.key));
+        Ok(stored)
+    }
+
+    fn stat(&self, key: &str) -> io::Result<StoredFile> {
+        validate_key(key)?;
+
+        let url = self.bucket.head_object(Some(&self.credentials), key).sign(SIGNATURE_TTL);
+        let response = self.send(self.client.head(url))?;
+        let header = |name| response.headers().get(name).and_then(|v| v.to_str().ok());
+
+        Ok(StoredFile {
+            key: key.to_string(),
+            size: header(reqwest::header::CONTENT_LENGTH).and_then(|v| v.parse().ok()).unwrap_or_default(),
+            modified: header(reqwest::header::LAST_MODIFIED)
+                .and_then(|v| DateTime::parse_from_rfc2822(v).ok())
+                .map(SystemTime::from),
+            mode: None,
+        })
+    }
+
+    fn read(&self, key: &str) -> io::Result<Box<dyn Read + Send>> {
+        self.open(key, 0)
+    }
+
+    fn open(&self, key: &str, offset: u64) -> io::Result<Box<dyn Read + Send>> {
+        validate_key(key)?;
+
+        let url = self.bucket.get_object(Some(&self.credentials), key).sign(SIGNATURE_TTL);
+        let mut request = self.client.get(url);
+        if offset > 0 {
+            request = request.header(reqwest::header::RANGE, format!("bytes={}-", offset));
+        }
+        let response = self.send(request)?;
+
+        Ok(Box::new(S3Reader { response, runtime: self.runtime.clone(), chunk: Default::default() }))
+    }
+
+    fn delete(&self, key: &str) -> io::Result<u64> {
+        let size = self.stat(key)?.size;
+
+        let url = self.bucket.delete_object(Some(&self.credentials), key).sign(SIGNATURE_TTL);
+        self.send(self.client.delete(url))?;
+
+        Ok(size)
+    }
+
+    // Object stores have no folders, only keys sharing the prefix
+    fn delete_dir(&self, dir: &str) -> io::Result<(usize, u64)> {
+        validate_key(dir)?;
+
+        let objects = self.objects(&format!("{}/", dir))?;
+        if objects.is_empty() {
+            return Err(io::ErrorKind::NotFound.into());
+        }
+
+        let mut usage = (0, 0);
+        for object in objects {
+            let url = self.bucket.delete_object(Some(&self.credentials), &object.key).sign(SIGNATURE_TTL);
+            self.send(self.client.delete(url))?;
+            if !hidden(&object.key) {
+                usage.0 += 1;
+                usage.1 += object.size;
+            }
+        }
+
+        Ok(usage)
+    }
+}
+
+// Streams an object body chunk by chunk instead of buffering it whole
+struct S3Reader {
+    response: reqwest::Response,
+    runtime: tokio::runtime::Handle,
+    chunk: axum::body::Bytes,
+}
+
+impl Read for S3Reader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.chunk.is_empty() {
+            match self.runtime.block_on(self.response.chunk()).map_err(io::Error::other)? {
+                Some(chunk) => self.chunk = chunk,
+                None => return Ok(0),
+            }
+        }
+
+        let len = buf.len().min(self.chunk.len());
+        buf[..len].copy_from_slice(&self.chunk.split_to(len));
+        Ok(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::TempDir;
+
+    #[test]
+    fn local_save_never_replaces_a_stored_file() {
+        let dir = TempDir::new();
+        let storage = LocalFs::new(dir.path().to_path_buf());
+        let (first, second) = (dir.path().join(".first.tmp"), dir.path().join(".second.tmp"));
+        fs::write(&first, "first").unwrap();
+        fs::write(&second, "second").unwrap();
+
+        storage.save("2024-01-01/app.log", &first).unwrap();
+        let e = storage.save("2024-01-01/app.log", &second).unwrap_err();
+
+        assert_eq!(e.kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(fs::read_to_string(dir.path().join("2024-01-01/app.log")).unwrap(), "first");
+        assert!(!first.exists());
+        assert!(second.exists());
+    }
+
+    #[test]
+    fn local_open_starts_at_the_offset() {
+        let dir = TempDir::new();
+        fs::create_dir_all(dir.path().join("2024-01-01")).unwrap();
+        fs::write(dir.path().join("2024-01-01/app.log"), "0123456789").unwrap();
+        let storage = LocalFs::new(dir.path().to_path_buf());
+
+        let mut rest = String::new();
+        storage.open("2024-01-01/app.log", 4).unwrap().read_to_string(&mut rest).unwrap();
+        assert_eq!(rest, "456789");
+        assert_eq!(storage.stat("2024-01-01/app.log").unwrap().size, 10);
+        assert!(storage.stat("2024-01-01").is_err_and(|e| e.kind() == io::ErrorKind::NotFound));
+    }
+
+    #[test]
+    fn local_delete_dir_removes_hidden_files_but_only_counts_visible_ones() {
+        let dir = TempDir::new();
+        fs::create_dir_all(dir.path().join("2024-01-01")).unwrap();
+        fs::write(dir.path().join("2024-01-01/app.log"), "app").unwrap();
+        fs::write(dir.path().join("2024-01-01/.app.log.sha256"), "checksum").unwrap();
+        let storage = LocalFs::new(dir.path().to_path_buf());
+
+        assert_eq!(storage.delete_dir("2024-01-01").unwrap(), (1, 3));
+        assert!(!dir.path().join("2024-01-01").exists());
+        assert_eq!(storage.delete_dir("2024-01-01").unwrap_err().kind(), io::ErrorKind::NotFound);
+        assert_eq!(storage.delete_dir("..").unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn local_deletes_only_prune_the_blob_they_linked_to() {
+        let dir = TempDir::new();
+        let storage = LocalFs::new(dir.path().to_path_buf());
+        let digest = files::sha256(&b"same"[..]).unwrap();
+        for key in ["2024-01-01/a.log", "2024-01-02/b.log"] {
+            let local = dir.path().join(".upload.tmp");
+            fs::write(&local, "same").unwrap();
+            storage.save_deduplicated(key, &local, &digest).unwrap();
+        }
+        fs::write(dir.path().join("2024-01-01/.a.log.sha256"), &digest).unwrap();
+        // Left for the retention sweep, which scans every blob
+        let orphan = blob_dir(dir.path()).join("0".repeat(64));
+        fs::write(&orphan, "orphan").unwrap();
+
+        storage.delete("2024-01-01/a.log").unwrap();
+        assert!(blob_dir(dir.path()).join(&digest).exists());
+        storage.delete_dir("2024-01-02").unwrap();
+        assert!(!blob_dir(dir.path()).join(&digest).exists());
+        assert!(orphan.exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn local_delete_dir_never_follows_links() {
+        let (dir, outside) = (TempDir::new(), TempDir::new());
+        fs::write(outside.path().join("keep.log"), "keep").unwrap();
+        std::os::unix::fs::symlink(outside.path(), dir.path().join("2024-01-01")).unwrap();
+        let storage = LocalFs::new(dir.path().to_path_buf());
+
+        assert_eq!(storage.delete_dir("2024-01-01").unwrap_err().kind(), io::ErrorKind::NotFound);
+        assert!(outside.path().join("keep.log").exists());
+    }
+
+    #[test]
+    fn prefixed_keys_stay_relative_to_the_prefix() {
+        let dir = TempDir::new();
+        fs::create_dir_all(dir.path().join("acme/2024-01-01")).unwrap();
+        fs::write(dir.path().join("acme/2024-01-01/app.log"), "app").unwrap();
+        let storage = Prefixed::new(Arc::new(LocalFs::new(dir.path().to_path_buf())), "acme/".to_string());
+
+        assert_eq!(storage.stat("2024-01-01/app.log").unwrap().key, "2024-01-01/app.log");
+        assert_eq!(storage.delete_dir("2024-01-01").unwrap(), (1, 3));
+        assert!(dir.path().join("acme").is_dir());
+    }
+
+    // Just enough of S3 for conditional and multipart uploads, keeping whatever was stored
+    #[derive(Default)]
+    struct MockS3 {
+        objects: std::collections::HashMap<String, Vec<u8>>,
+        parts: std::collections::BTreeMap<u16, Vec<u8>>,
+        largest_request: usize,
+    }
+
+    async fn mock_s3(
+        axum::extract::State(mock): axum::extract::State<Arc<std::sync::Mutex<MockS3>>>,
+        method: axum::http::Method,
+        uri: axum::http::Uri,
+        headers: axum::http::HeaderMap,
+        body: axum::body::Bytes,
+    ) -> axum::response::Response {
+        use axum::{http::{Method, StatusCode}, response::IntoResponse};
+
+        let mut mock = mock.lock().unwrap();
+        mock.largest_request = mock.largest_request.max(body.len());
+        let key = uri.path().trim_start_matches("/logs/").to_string();
+        let query = uri.query().unwrap_or_default();
+        let param = |name: &str| query.split('&').find_map(|pair| pair.strip_prefix(&format!("{}=", name)).map(str::to_string));
+        let conditional = headers.get("if-none-match").is_some_and(|v| v == "*");
+
+        match method {
+            Method::POST if param("uploads").is_some() => {
+                mock.parts.clear();
+                "<InitiateMultipartUploadResult xmlns=\"http://s3.amazonaws.com/doc/2006-03-01/\"><UploadId>upload</UploadId></InitiateMultipartUploadResult>".into_response()
+            }
+            Method::PUT if let Some(number) = param("partNumber") => {
+                let number: u16 = number.parse().unwrap();
+                mock.parts.insert(number, body.to_vec());
+                [("etag", format!("\"part-{}\"", number))].into_response()
+            }
+            Method::POST if param("uploadId").is_some() => {
+                if conditional && mock.objects.contains_key(&key) {
+                    return StatusCode::PRECONDITION_FAILED.into_response();
+                }
+                let object = std::mem::take(&mut mock.parts).into_values().flatten().collect();
+                mock.objects.insert(key, object);
+                "<CompleteMultipartUploadResult/>".into_response()
+            }
+            Method::PUT => {
+                if conditional && mock.objects.contains_key(&key) {
+                    return StatusCode::PRECONDITION_FAILED.into_response();
+                }
+                mock.objects.insert(key, body.to_vec());
+                StatusCode::OK.into_response()
+            }
+            Method::HEAD | Method::GET => match mock.objects.get(&key) {
+                Some(object) => object.clone().into_response(),
+                None => StatusCode::NOT_FOUND.into_response(),
+            },
+            _ => StatusCode::NOT_IMPLEMENTED.into_response(),
+        }
+    }
+
+    async fn s3() -> (Arc<S3>, Arc<std::sync::Mutex<MockS3>>) {
+        let mock = Arc::new(std::sync::Mutex::new(MockS3::default()));
+        let router = axum::Router::new()
+            .fallback(mock_s3)
+            .layer(axum::extract::DefaultBodyLimit::disable())
+            .with_state(mock.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, router).await });
+
+        (Arc::new(S3::new(&endpoint, "logs", "us-east-1", "access", "secret").unwrap()), mock)
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn s3_save_never_replaces_a_stored_object() {
+        let (s3, mock) = s3().await;
+        let dir = TempDir::new();
+        let (first, second) = (dir.path().join(".first.tmp"), dir.path().join(".second.tmp"));
+        fs::write(&first, "first").unwrap();
+        fs::write(&second, "second").unwrap();
+
+        let s3 = s3 as Arc<dyn StorageBackend>;
+        let (saved_first, saved_second) = (first.clone(), second.clone());
+        let (exists_before, saved, exists_after, refused) = blocking(&s3, move |s3| {
+            Ok((
+                s3.exists("2024-01-01/app.log")?,
+                s3.save("2024-01-01/app.log", &saved_first),
+                s3.exists("2024-01-01/app.log")?,
+                s3.save("2024-01-01/app.log", &saved_second),
+            ))
+        })
+        .await
+        .unwrap();
+
+        assert!(!exists_before && exists_after);
+        saved.unwrap();
+        assert_eq!(refused.unwrap_err().kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(mock.lock().unwrap().objects["2024-01-01/app.log"], b"first");
+        assert!(!first.exists());
+        assert!(second.exists());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn s3_saves_large_files_in_parts() {
+        let (s3, mock) = s3().await;
+        let dir = TempDir::new();
+        let local = dir.path().join(".large.tmp");
+        let contents: Vec<u8> = (0..2 * PART_SIZE + 1).map(|i| (i % 251) as u8).collect();
+        fs::write(&local, &contents).unwrap();
+
+        let s3 = s3 as Arc<dyn StorageBackend>;
+        blocking(&s3, move |s3| s3.save("2024-01-01/large.log", &local)).await.unwrap();
+
+        let mock = mock.lock().unwrap();
+        assert!(mock.objects["2024-01-01/large.log"] == contents);
+        assert_eq!(mock.largest_request as u64, PART_SIZE);
+    }
+}
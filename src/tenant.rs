@@ -0,0 +1,80 @@
+use std::convert::Infallible;
+
+use axum::{extract::FromRequestParts, http::request::Parts};
+
+use crate::files;
+
+// The tenant a request was authenticated as. Requests without one see the whole data dir.
+#[derive(Clone, Default)]
+pub struct Tenant(pub Option<String>);
+
+impl Tenant {
+    // Prefix of the tenant's files, relative to the data dir
+    pub fn prefix(&self) -> String {
+        match &self.0 {
+            Some(id) => format!("{}/", id),
+            None => String::new(),
+        }
+    }
+
+    pub fn scope(&self, key: &str) -> String {
+        format!("{}{}", self.prefix(), key)
+    }
+}
+
+// Set by the auth middleware, never rejects a request
+impl<S: Send + Sync> FromRequestParts<S> for Tenant {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(parts.extensions.get::<Tenant>().cloned().unwrap_or_default())
+    }
+}
+
+// Parses a `TOKEN=TENANT` pair. Tenant IDs become a folder name, so only plain names are allowed.
+pub fn parse_mapping(value: &str) -> Result<(String, String), String> {
+    let (token, tenant) = value.split_once('=')
+        .ok_or_else(|| "expected TOKEN=TENANT".to_string())?;
+
+    if token.is_empty() || !valid_id(tenant) {
+        return Err(format!("invalid tenant mapping: {}", tenant));
+    }
+
+    Ok((token.to_string(), tenant.to_string()))
+}
+
+// A tenant folder sits next to the day folders of untenanted uploads, so one named like a day
+// would be listed, downloaded and swept as one
+pub fn valid_id(tenant: &str) -> bool {
+    !tenant.is_empty()
+        && files::parse_day(tenant).is_none()
+        && !tenant.starts_with('.')
+        && tenant.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plain_names_are_valid() {
+        for tenant in ["acme", "globex-eu", "tenant_2", "2024"] {
+            assert!(valid_id(tenant), "{}", tenant);
+        }
+    }
+
+    #[test]
+    fn paths_and_hidden_names_are_rejected() {
+        for tenant in ["", ".acme", "..", "acme/eu", "acme.eu", "ac me"] {
+            assert!(!valid_id(tenant), "{:?}", tenant);
+        }
+    }
+
+    #[test]
+    fn day_folders_are_rejected() {
+        assert!(!valid_id("2024-05-01"));
+        assert!(parse_mapping("token=2024-05-01").is_err());
+        // Only real days collide with a day folder
+        assert!(valid_id("2024-13-01"));
+    }
+}
@@ -0,0 +1,71 @@
+use axum::{body::Body, http::{Request, StatusCode, header}};
+
+use super::{TestApp, unzip};
+
+#[tokio::test]
+async fn upload_list_and_download_round_trip() {
+    let app = TestApp::new(&[]);
+
+    let uploaded = app.upload("app.log", b"hello world").await;
+    assert_eq!(uploaded.status, StatusCode::OK);
+    let stored_path = uploaded.json()["files"][0]["stored_path"].as_str().unwrap().to_string();
+    assert!(stored_path.ends_with("_app.log"));
+    assert!(app.dir.path().join(&stored_path).is_file());
+
+    let listed = app.get("/files").await;
+    assert_eq!(listed.status, StatusCode::OK);
+    let listed = listed.json();
+    assert_eq!(listed["total"], 1);
+    assert_eq!(listed["files"][0]["path"], stored_path.as_str());
+    assert_eq!(listed["files"][0]["size"], 11);
+
+    let downloaded = app.get("/download").await;
+    assert_eq!(downloaded.status, StatusCode::OK);
+    assert_eq!(downloaded.headers["content-type"], "application/zip");
+    assert_eq!(unzip(&downloaded.body), vec![(stored_path, b"hello world".to_vec())]);
+}
+
+#[tokio::test]
+async fn state_is_built_over_the_given_data_dir() {
+    let app = TestApp::new(&["--max-upload-size", "1234"]);
+
+    assert_eq!(app.state.config.data_dir, app.dir.path());
+    assert_eq!(app.state.config.max_upload_size, 1234);
+}
+
+#[tokio::test]
+async fn single_files_download_whole_or_in_ranges() {
+    let app = TestApp::new(&[]);
+    std::fs::create_dir_all(app.dir.path().join("2024-01-01")).unwrap();
+    std::fs::write(app.dir.path().join("2024-01-01/app.log"), "0123456789").unwrap();
+
+    let whole = app.get("/files/2024-01-01/app.log").await;
+    assert_eq!(whole.status, StatusCode::OK);
+    assert_eq!(whole.headers["content-length"], "10");
+    assert_eq!(&whole.body[..], b"0123456789");
+
+    let request = Request::get("/files/2024-01-01/app.log").header(header::RANGE, "bytes=3-5").body(Body::empty()).unwrap();
+    let partial = app.send(request).await;
+    assert_eq!(partial.status, StatusCode::PARTIAL_CONTENT);
+    assert_eq!(partial.headers["content-range"], "bytes 3-5/10");
+    assert_eq!(&partial.body[..], b"345");
+
+    assert_eq!(app.get("/files/2024-01-01/missing.log").await.status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn search_matches_the_original_name_too() {
+    let app = TestApp::new(&[]);
+    let stored_path = app.upload("nightly/App Server.log", b"boot").await.json()["files"][0]["stored_path"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    assert!(!stored_path.contains("nightly"));
+
+    for q in ["app server", "NIGHTLY"] {
+        let found = app.get(&format!("/search?q={}", q.replace(' ', "%20"))).await;
+        assert_eq!(found.status, StatusCode::OK);
+        assert_eq!(found.json()[0]["path"], stored_path.as_str(), "{}", q);
+    }
+    assert_eq!(app.get("/search?q=daily").await.json(), serde_json::json!([]));
+}
@@ -0,0 +1,206 @@
+use axum::{body::Body, http::{Request, StatusCode, header}};
+
+use super::{TestApp, stored_files, unzip};
+
+#[tokio::test]
+async fn empty_data_dir_downloads_an_empty_zip() {
+    let app = TestApp::new(&[]);
+
+    let response = app.get("/download").await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.headers["x-file-count"], "0");
+    assert!(unzip(&response.body).is_empty());
+}
+
+#[tokio::test]
+async fn missing_data_dir_is_recreated_for_an_empty_zip() {
+    let app = TestApp::new(&[]);
+    std::fs::remove_dir(app.dir.path()).unwrap();
+
+    let response = app.get("/download").await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.headers["x-file-count"], "0");
+    assert!(unzip(&response.body).is_empty());
+    assert!(app.dir.path().is_dir());
+}
+
+#[tokio::test]
+async fn populated_data_dir_downloads_every_file() {
+    let app = TestApp::new(&[]);
+    std::fs::create_dir_all(app.dir.path().join("2024-01-01")).unwrap();
+    std::fs::write(app.dir.path().join("2024-01-01/a.log"), "a").unwrap();
+    std::fs::write(app.dir.path().join("2024-01-01/b.log"), "bb").unwrap();
+    std::fs::create_dir_all(app.dir.path().join("2024-01-02")).unwrap();
+    std::fs::write(app.dir.path().join("2024-01-02/c.log"), "ccc").unwrap();
+
+    let response = app.get("/download").await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.headers["x-file-count"], "3");
+    assert_eq!(unzip(&response.body), vec![
+        ("2024-01-01/a.log".to_string(), b"a".to_vec()),
+        ("2024-01-01/b.log".to_string(), b"bb".to_vec()),
+        ("2024-01-02/c.log".to_string(), b"ccc".to_vec()),
+    ]);
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn symlinks_are_left_out_of_listings_and_archives() {
+    use std::os::unix::fs::symlink;
+
+    let app = TestApp::new(&[]);
+    let outside = super::TempDir::new();
+    std::fs::write(outside.path().join("secret"), "secret").unwrap();
+
+    let day = app.dir.path().join("2024-01-01");
+    std::fs::create_dir_all(&day).unwrap();
+    std::fs::write(day.join("app.log"), "log").unwrap();
+    symlink(outside.path().join("secret"), day.join("linked.log")).unwrap();
+    symlink(outside.path(), day.join("linked")).unwrap();
+    symlink(outside.path(), app.dir.path().join("2024-01-02")).unwrap();
+
+    let listed = app.get("/files").await.json();
+    assert_eq!(listed["total"], 1);
+    assert_eq!(listed["files"][0]["path"], "2024-01-01/app.log");
+
+    let downloaded = app.get("/download").await;
+    assert_eq!(downloaded.headers["x-file-count"], "1");
+    assert_eq!(unzip(&downloaded.body), vec![("2024-01-01/app.log".to_string(), b"log".to_vec())]);
+
+    // Asked for by name, the link resolves outside of the data dir and is refused
+    let fetched = app.get("/files/2024-01-01/linked.log").await;
+    assert_eq!(fetched.status, StatusCode::BAD_REQUEST);
+    assert!(!String::from_utf8_lossy(&fetched.body).contains("secret"));
+}
+
+fn write_days(app: &TestApp) {
+    for (day, name, contents) in [("2024-01-01", "a.log", "a"), ("2024-01-01", "b.log", "bb"), ("2024-01-02", "c.log", "ccc"), ("2024-01-03", "d.log", "dddd")] {
+        std::fs::create_dir_all(app.dir.path().join(day)).unwrap();
+        std::fs::write(app.dir.path().join(day).join(name), contents).unwrap();
+    }
+}
+
+#[tokio::test]
+async fn deleting_a_day_removes_its_files() {
+    let app = TestApp::new(&[]);
+    write_days(&app);
+
+    let response = app.send(Request::delete("/download/2024-01-01").body(Body::empty()).unwrap()).await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.json()["deleted_files"], 2);
+    assert!(!app.dir.path().join("2024-01-01").exists());
+    assert_eq!(stored_files(app.dir.path()), ["2024-01-02/c.log", "2024-01-03/d.log"]);
+
+    let response = app.send(Request::delete("/download/2024-01-01").body(Body::empty()).unwrap()).await;
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn deleting_a_range_removes_the_days_in_it() {
+    let app = TestApp::new(&[]);
+    write_days(&app);
+
+    let response = app.send(Request::delete("/download?from=2024-01-02").body(Body::empty()).unwrap()).await;
+    assert_eq!(response.status, StatusCode::OK);
+    let deleted = response.json();
+    assert_eq!(deleted["dates"], serde_json::json!(["2024-01-02", "2024-01-03"]));
+    assert_eq!(deleted["deleted_files"], 2);
+    assert_eq!(stored_files(app.dir.path()), ["2024-01-01/a.log", "2024-01-01/b.log"]);
+}
+
+#[tokio::test]
+async fn archives_are_never_compressed_again() {
+    let app = TestApp::new(&[]);
+    write_days(&app);
+
+    for (format, content_type) in [("zip", "application/zip"), ("tar", "application/x-tar"), ("targz", "application/gzip")] {
+        let request = Request::get(format!("/download?format={}", format))
+            .header(header::ACCEPT_ENCODING, "gzip, br")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.send(request).await;
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(response.headers[header::CONTENT_TYPE], content_type);
+        assert!(!response.headers.contains_key(header::CONTENT_ENCODING), "{}", format);
+    }
+}
+
+#[tokio::test]
+async fn negotiated_archives_vary_on_accept() {
+    let app = TestApp::new(&[]);
+    write_days(&app);
+
+    let request = Request::get("/download").header(header::ACCEPT, "application/x-tar").body(Body::empty()).unwrap();
+    let response = app.send(request).await;
+    assert_eq!(response.headers[header::CONTENT_TYPE], "application/x-tar");
+    assert!(response.headers.get_all(header::VARY).iter().any(|v| v == "accept"));
+
+    let response = app.get("/download/2024-01-01").await;
+    assert_eq!(response.headers[header::CONTENT_TYPE], "application/zip");
+    assert!(response.headers.get_all(header::VARY).iter().any(|v| v == "accept"));
+}
+
+#[tokio::test]
+async fn only_downloads_handed_out_are_audited() {
+    let audit = super::TempDir::new();
+    let audit_log = audit.path().join("audit.log");
+    let app = TestApp::new(&["--audit-log", audit_log.to_str().unwrap()]);
+    write_days(&app);
+    let audited = || std::fs::read_to_string(&audit_log).unwrap_or_default().lines().count();
+
+    for uri in ["/download", "/download/2024-01-01"] {
+        let response = app.get(uri).await;
+        assert_eq!(response.status, StatusCode::OK);
+        let before = audited();
+
+        let revalidated = Request::get(uri)
+            .header(header::IF_NONE_MATCH, response.headers[header::ETAG].clone())
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(app.send(revalidated).await.status, StatusCode::NOT_MODIFIED);
+        assert_eq!(app.send(Request::head(uri).body(Body::empty()).unwrap()).await.status, StatusCode::OK);
+        assert_eq!(audited(), before, "{}", uri);
+    }
+    assert_eq!(audited(), 2);
+}
+
+#[tokio::test]
+async fn deletes_finish_when_the_audit_log_fails() {
+    let app = TestApp::with(&[], |state| state.audit = Some(crate::audit::AuditLog::failing(true)));
+    write_days(&app);
+    std::fs::write(app.dir.path().join("2024-01-01/.a.log.sha256"), "checksum").unwrap();
+    std::fs::write(app.dir.path().join("2024-01-01/.a.log.meta.json"), "{}").unwrap();
+
+    let delete = |uri: &str| app.send(Request::delete(uri).body(Body::empty()).unwrap());
+    assert_eq!(delete("/files/2024-01-01/a.log").await.status, StatusCode::INTERNAL_SERVER_ERROR);
+    assert!(!app.dir.path().join("2024-01-01/.a.log.sha256").exists());
+    assert!(!app.dir.path().join("2024-01-01/.a.log.meta.json").exists());
+    assert_eq!(stored_files(app.dir.path()), ["2024-01-01/b.log", "2024-01-02/c.log", "2024-01-03/d.log"]);
+
+    assert_eq!(delete("/download/2024-01-02").await.status, StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(delete("/download?from=2024-01-03").await.status, StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(stored_files(app.dir.path()), ["2024-01-01/b.log"]);
+}
+
+#[tokio::test]
+async fn deterministic_selections_ignore_the_listed_order() {
+    let app = TestApp::new(&[]);
+    write_days(&app);
+    let select = |uri: &str, paths: &str| {
+        Request::post(uri)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(format!(r#"{{"paths": {}}}"#, paths)))
+            .unwrap()
+    };
+
+    let forward = app.send(select("/download?deterministic=true", r#"["2024-01-01/a.log", "2024-01-02/c.log"]"#)).await;
+    let backward = app.send(select("/download?deterministic=true", r#"["2024-01-02/c.log", "2024-01-01/a.log"]"#)).await;
+    assert_eq!(forward.status, StatusCode::OK);
+    assert_eq!(forward.body, backward.body);
+    assert_eq!(forward.headers[header::ETAG], backward.headers[header::ETAG]);
+    assert_eq!(unzip(&backward.body)[0].0, "2024-01-01/a.log");
+
+    let encrypted = app.send(select("/download?deterministic=true&password=secret", r#"["2024-01-01/a.log"]"#)).await;
+    assert_eq!(encrypted.status, StatusCode::BAD_REQUEST);
+    assert!(encrypted.json()["error"].as_str().unwrap().ends_with("Encrypted archives can't be deterministic"));
+}
@@ -0,0 +1,139 @@
+// Drives the router in-process against a throwaway data dir, no socket involved
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use axum::{
+    Router,
+    body::{Body, Bytes, to_bytes},
+    http::{HeaderMap, Request, StatusCode, header},
+};
+use clap::Parser;
+use serde_json::Value;
+use tower::ServiceExt;
+
+use crate::{AppState, config::{AppConfig, Args}, create_app};
+
+mod api;
+mod download;
+mod upload;
+
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new() -> Self {
+        let path = std::env::temp_dir().join(format!("eotwsink-test-{}", uuid::Uuid::new_v4().simple()));
+        fs::create_dir_all(&path).unwrap();
+        Self(path)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+// Configuration as parsed from the command line, on top of a data dir
+pub fn config(data_dir: &Path, args: &[&str]) -> AppConfig {
+    let mut argv = vec!["eotwsink", "--data-dir", data_dir.to_str().unwrap()];
+    argv.extend_from_slice(args);
+    AppConfig::from(Args::try_parse_from(argv).unwrap())
+}
+
+pub struct Response {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Bytes,
+}
+
+impl Response {
+    pub fn json(&self) -> Value {
+        serde_json::from_slice(&self.body).unwrap()
+    }
+}
+
+pub struct TestApp {
+    // Dropped last, the state may still hold files in it open
+    pub state: Arc<AppState>,
+    router: Router,
+    pub dir: TempDir,
+}
+
+impl TestApp {
+    pub fn new(args: &[&str]) -> Self {
+        Self::with(args, |_| {})
+    }
+
+    // Like new, with the state adjusted before the router is built over it
+    pub fn with(args: &[&str], adjust: impl FnOnce(&mut AppState)) -> Self {
+        let dir = TempDir::new();
+        let mut state = AppState::new(config(dir.path(), args));
+        adjust(&mut state);
+        let state = Arc::new(state);
+        let router = create_app(state.clone());
+        Self { state, router, dir }
+    }
+
+    pub async fn send(&self, request: Request<Body>) -> Response {
+        let response = self.router.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let headers = response.headers().clone();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        Response { status, headers, body }
+    }
+
+    pub async fn get(&self, uri: &str) -> Response {
+        self.send(Request::get(uri).body(Body::empty()).unwrap()).await
+    }
+
+    pub async fn upload(&self, file_name: &str, contents: &[u8]) -> Response {
+        self.send(multipart("/upload", "file", file_name, contents)).await
+    }
+}
+
+const BOUNDARY: &str = "eotwsink-test-boundary";
+
+// A multipart/form-data POST carrying a single file
+pub fn multipart(uri: &str, field: &str, file_name: &str, contents: &[u8]) -> Request<Body> {
+    let mut body = format!(
+        "--{}\r\nContent-Disposition: form-data; name=\"{}\"; filename=\"{}\"\r\nContent-Type: text/plain\r\n\r\n",
+        BOUNDARY, field, file_name
+    ).into_bytes();
+    body.extend_from_slice(contents);
+    body.extend_from_slice(format!("\r\n--{}--\r\n", BOUNDARY).as_bytes());
+
+    Request::post(uri)
+        .header(header::CONTENT_TYPE, format!("multipart/form-data; boundary={}", BOUNDARY))
+        .header(header::CONTENT_LENGTH, body.len())
+        .body(Body::from(body))
+        .unwrap()
+}
+
+// Paths of the files in a data dir, skipping sidecars and partial uploads like listings do
+pub fn stored_files(data_dir: &Path) -> Vec<String> {
+    let mut files: Vec<_> = crate::files::walk(data_dir).map(|(_, key)| key).collect();
+    files.sort();
+    files
+}
+
+// Names and contents of every entry in a zip
+pub fn unzip(bytes: &[u8]) -> Vec<(String, Vec<u8>)> {
+    use std::io::Read;
+
+    let mut zip = zip::ZipArchive::new(std::io::Cursor::new(bytes)).unwrap();
+    (0..zip.len())
+        .map(|i| {
+            let mut entry = zip.by_index(i).unwrap();
+            let mut contents = Vec::new();
+            entry.read_to_end(&mut contents).unwrap();
+            (entry.name().to_string(), contents)
+        })
+        .collect()
+}
@@ -0,0 +1,269 @@
+use std::{sync::Arc, time::Duration};
+
+use axum::{body::Body, http::{Request, StatusCode, header}};
+
+use super::{TestApp, multipart, stored_files};
+
+#[tokio::test]
+async fn upload_over_the_size_limit_is_rejected() {
+    let app = TestApp::new(&["--max-upload-size", "1024"]);
+
+    let response = app.upload("big.log", &[b'x'; 1025]).await;
+    assert_eq!(response.status, StatusCode::PAYLOAD_TOO_LARGE);
+    let body = response.json();
+    assert_eq!(body["code"], "too_large");
+    assert_eq!(body["error"], "Uploads are limited to 1024 bytes.");
+}
+
+#[tokio::test]
+async fn upload_over_the_limit_without_content_length_is_rejected() {
+    let app = TestApp::new(&["--max-upload-size", "1024"]);
+
+    // Chunked bodies are only caught once the limit is read past
+    let request = multipart("/upload", "file", "big.log", &[b'x'; 4096]);
+    let (mut parts, body) = request.into_parts();
+    parts.headers.remove(header::CONTENT_LENGTH);
+    let body = Body::from_stream(body.into_data_stream());
+
+    let response = app.send(Request::from_parts(parts, body)).await;
+    assert_eq!(response.status, StatusCode::PAYLOAD_TOO_LARGE);
+    assert_eq!(response.json()["code"], "too_large");
+    assert!(stored_files(app.dir.path()).is_empty());
+}
+
+#[tokio::test]
+async fn upload_under_the_size_limit_is_stored() {
+    let app = TestApp::new(&["--max-upload-size", "1024"]);
+
+    // The limit covers the whole body, multipart framing included
+    let response = app.upload("small.log", &[b'x'; 512]).await;
+    assert_eq!(response.status, StatusCode::OK);
+}
+
+// Uploads of one name in the same second either get their own file or a 409, never a file
+// made of several bodies or one replaced by another
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn concurrent_uploads_of_one_name_never_mix() {
+    let app = Arc::new(TestApp::new(&[]));
+
+    let uploads: Vec<_> = (0..8u8)
+        .map(|i| {
+            let app = app.clone();
+            tokio::spawn(async move {
+                let contents = vec![b'a' + i; 256 * 1024];
+                let response = app.upload("same.log", &contents).await;
+                (response, contents)
+            })
+        })
+        .collect();
+
+    let mut stored = Vec::new();
+    let finished = tokio::time::timeout(Duration::from_secs(10), async {
+        for upload in uploads {
+            let (response, contents) = upload.await.unwrap();
+            match response.status {
+                StatusCode::OK => {
+                    let path = response.json()["files"][0]["stored_path"].as_str().unwrap().to_string();
+                    stored.push((path, contents));
+                }
+                StatusCode::CONFLICT => assert_eq!(response.json()["code"], "conflict"),
+                status => panic!("unexpected status {}", status),
+            }
+        }
+    });
+    finished.await.expect("uploads didn't finish in time");
+
+    assert!(!stored.is_empty());
+    let mut paths: Vec<_> = stored.iter().map(|(path, _)| path.clone()).collect();
+    paths.sort();
+    assert_eq!(stored_files(app.dir.path()), paths);
+    for (path, contents) in &stored {
+        assert_eq!(&std::fs::read(app.dir.path().join(path)).unwrap(), contents);
+    }
+}
+
+#[tokio::test]
+async fn files_are_only_accepted_in_the_upload_field() {
+    let app = TestApp::new(&[]);
+
+    let response = app.send(multipart("/upload", "logfile", "app.log", b"log")).await;
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+    assert_eq!(
+        response.json()["error"],
+        "There is something wrong with your request: Unexpected field logfile, files are uploaded in file"
+    );
+    assert!(stored_files(app.dir.path()).is_empty());
+
+    assert_eq!(app.send(multipart("/upload", "file", "app.log", b"log")).await.status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn upload_field_is_configurable() {
+    let app = TestApp::new(&["--upload-field", "logs"]);
+
+    assert_eq!(app.send(multipart("/upload", "file", "app.log", b"log")).await.status, StatusCode::BAD_REQUEST);
+    assert_eq!(app.send(multipart("/upload", "logs", "app.log", b"log")).await.status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn plain_form_values_are_ignored() {
+    let app = TestApp::new(&[]);
+
+    let body = "--b\r\nContent-Disposition: form-data; name=\"csrf\"\r\n\r\ntoken\r\n\
+        --b\r\nContent-Disposition: form-data; name=\"file\"; filename=\"app.log\"\r\n\r\nlog\r\n--b--\r\n";
+    let request = Request::post("/upload")
+        .header(header::CONTENT_TYPE, "multipart/form-data; boundary=b")
+        .body(Body::from(body))
+        .unwrap();
+
+    let response = app.send(request).await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.json()["files"].as_array().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn upload_without_a_file_is_rejected() {
+    let app = TestApp::new(&[]);
+
+    let body = "--b\r\nContent-Disposition: form-data; name=\"file\"\r\n\r\nnot a file\r\n--b--\r\n";
+    let request = Request::post("/upload")
+        .header(header::CONTENT_TYPE, "multipart/form-data; boundary=b")
+        .body(Body::from(body))
+        .unwrap();
+
+    assert_eq!(app.send(request).await.status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn stalled_upload_times_out_with_a_json_error() {
+    use futures_util::StreamExt;
+
+    let app = TestApp::new(&["--request-timeout", "1"]);
+
+    // The start of an upload whose remaining bytes never arrive
+    let request = multipart("/upload", "file", "stalled.log", &[b'x'; 4096]);
+    let (mut parts, body) = request.into_parts();
+    parts.headers.remove(header::CONTENT_LENGTH);
+    let start = axum::body::to_bytes(body, usize::MAX).await.unwrap().slice(..2048);
+    let stalled = futures_util::stream::once(async move { Ok::<_, std::io::Error>(start) })
+        .chain(futures_util::stream::pending());
+
+    let response = tokio::time::timeout(Duration::from_secs(10), app.send(Request::from_parts(parts, Body::from_stream(stalled))))
+        .await
+        .unwrap();
+    assert_eq!(response.status, StatusCode::REQUEST_TIMEOUT);
+    let body = response.json();
+    assert_eq!(body["code"], "timeout");
+    assert_eq!(body["error"], "Requests have to be answered within 1 seconds.");
+
+    let left: Vec<_> = walkdir::WalkDir::new(app.dir.path()).into_iter().filter_map(Result::ok).filter(|e| e.file_type().is_file()).collect();
+    assert!(left.is_empty(), "{:?}", left);
+}
+
+#[tokio::test]
+async fn uploads_past_the_quota_are_refused() {
+    let app = TestApp::new(&["--max-total-bytes", "1500"]);
+
+    assert_eq!(app.upload("first.log", &[b'x'; 1000]).await.status, StatusCode::OK);
+    let refused = app.upload("second.log", &[b'x'; 1000]).await;
+    assert_eq!(refused.status, StatusCode::INSUFFICIENT_STORAGE);
+    assert_eq!(refused.json()["code"], "quota_exceeded");
+    assert_eq!(app.state.used_bytes(), 1000);
+    assert_eq!(stored_files(app.dir.path()).len(), 1);
+}
+
+#[tokio::test]
+async fn idempotency_keys_never_cross_tenants() {
+    let app = TestApp::new(&["--auth-token", "root", "--tenant", "acme-token=acme"]);
+    let upload = |token: &str, key: &str| {
+        let mut request = multipart("/upload", "file", "app.log", b"hello");
+        request.headers_mut().insert(header::AUTHORIZATION, format!("Bearer {}", token).parse().unwrap());
+        request.headers_mut().insert("idempotency-key", key.parse().unwrap());
+        app.send(request)
+    };
+
+    let root = upload("root", "acme/x").await;
+    assert_eq!(root.status, StatusCode::OK);
+    let acme = upload("acme-token", "x").await;
+    assert_eq!(acme.status, StatusCode::OK);
+    assert!(!acme.headers.contains_key("idempotent-replayed"));
+
+    let replayed = upload("acme-token", "x").await;
+    assert_eq!(replayed.headers["idempotent-replayed"], "true");
+    assert_eq!(stored_files(app.dir.path()).len(), 2);
+}
+
+#[tokio::test]
+async fn uploads_the_audit_log_failed_to_record_are_removed() {
+    let app = TestApp::with(&[], |state| state.audit = Some(crate::audit::AuditLog::failing(true)));
+
+    assert_eq!(app.upload("app.log", b"hello").await.status, StatusCode::INTERNAL_SERVER_ERROR);
+    let left: Vec<_> = walkdir::WalkDir::new(app.dir.path()).into_iter().filter_map(Result::ok).filter(|e| e.file_type().is_file()).collect();
+    assert!(left.is_empty(), "{:?}", left);
+    assert_eq!(app.state.used_bytes(), 0);
+}
+
+async fn start_resumable(app: &TestApp) -> String {
+    let request = Request::post("/upload/init")
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(r#"{"filename": "big.log"}"#))
+        .unwrap();
+    let started = app.send(request).await;
+    assert_eq!(started.status, StatusCode::CREATED);
+    started.json()["upload_id"].as_str().unwrap().to_string()
+}
+
+async fn append_chunk(app: &TestApp, id: &str, chunk: &[u8]) -> super::Response {
+    let request = Request::patch(format!("/upload/{}", id))
+        .header(header::CONTENT_RANGE, format!("bytes 0-{}/*", chunk.len() - 1))
+        .body(Body::from(chunk.to_vec()))
+        .unwrap();
+    app.send(request).await
+}
+
+#[tokio::test]
+async fn resumable_chunks_are_held_to_the_upload_limit() {
+    let app = TestApp::new(&["--max-upload-size", "1024"]);
+    let id = start_resumable(&app).await;
+
+    let refused = append_chunk(&app, &id, &[b'x'; 1025]).await;
+    assert_eq!(refused.status, StatusCode::PAYLOAD_TOO_LARGE);
+    assert_eq!(refused.json()["code"], "too_large");
+    assert_eq!(append_chunk(&app, &id, &[b'x'; 1024]).await.status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn completing_resumable_uploads_reserves_the_quota() {
+    let app = TestApp::new(&["--max-total-bytes", "1500"]);
+    let (first, second) = (start_resumable(&app).await, start_resumable(&app).await);
+    // Neither counts against the quota before it's completed
+    assert_eq!(append_chunk(&app, &first, &[b'x'; 1000]).await.status, StatusCode::OK);
+    assert_eq!(append_chunk(&app, &second, &[b'x'; 1000]).await.status, StatusCode::OK);
+
+    let complete = |id: &str| app.send(Request::post(format!("/upload/{}/complete", id)).body(Body::empty()).unwrap());
+    assert_eq!(complete(&first).await.status, StatusCode::OK);
+    assert_eq!(complete(&second).await.status, StatusCode::INSUFFICIENT_STORAGE);
+    assert_eq!(app.state.used_bytes(), 1000);
+    assert_eq!(stored_files(app.dir.path()).len(), 1);
+}
+
+#[tokio::test]
+async fn idempotency_keys_only_replay_the_same_route() {
+    let app = TestApp::new(&[]);
+    let put = |file_name: &str| {
+        Request::put(format!("/upload/{}", file_name))
+            .header("idempotency-key", "nightly")
+            .body(Body::from("hello"))
+            .unwrap()
+    };
+    let mut post = multipart("/upload", "file", "app.log", b"hello");
+    post.headers_mut().insert("idempotency-key", "nightly".parse().unwrap());
+
+    for request in [post, put("a.log"), put("b.log")] {
+        let response = app.send(request).await;
+        assert_eq!(response.status, StatusCode::OK);
+        assert!(!response.headers.contains_key("idempotent-replayed"));
+    }
+    assert_eq!(app.send(put("a.log")).await.headers["idempotent-replayed"], "true");
+    assert_eq!(stored_files(app.dir.path()).len(), 3);
+}
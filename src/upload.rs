@@ -0,0 +1,595 @@
+use std::{path::PathBuf, sync::Arc};
+
+use axum::{
+    Json,
+    body::{Body, Bytes},
+    extract::{Path as UrlPath, Request, State},
+    http::{HeaderMap, StatusCode, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use axum_extra::extract::{Multipart, multipart::{MultipartError, MultipartRejection}};
+use futures_util::{Stream, StreamExt};
+use serde_json::{Value, json};
+use sha2::{Digest, Sha256};
+use tokio::io::AsyncWriteExt;
+
+use crate::{ApiError, AppState, audit, client::ClientIp, files, storage::{self, StorageBackend}, tenant::Tenant};
+
+const MAX_FILE_NAME_LEN: usize = 200;
+
+// Reduces a client supplied file name to a single harmless path segment. Directory parts are
+// dropped, leading dots removed so nothing ends up hidden, and control characters stripped.
+// Returns None when nothing usable is left.
+pub fn sanitize_filename(name: &str) -> Option<String> {
+    let last_segment = name.rsplit(['/', '\\']).next().unwrap_or_default();
+    let cleaned: String = sanitize_filename::sanitize(last_segment)
+        .trim_start_matches('.')
+        .chars()
+        .filter(|c| !c.is_control())
+        .take(MAX_FILE_NAME_LEN)
+        .collect();
+
+    let cleaned = cleaned.trim();
+    if cleaned.is_empty() {
+        return None;
+    }
+
+    Some(cleaned.to_string())
+}
+
+// Body limit violations surface as multipart errors, so map them to a proper 413
+fn multipart_error(context: &str, e: MultipartError, limit: usize) -> ApiError {
+    if e.status() == StatusCode::PAYLOAD_TOO_LARGE {
+        return ApiError::PayloadTooLarge(limit);
+    }
+
+    ApiError::BadRequest(format!("{}: {}", context, e))
+}
+
+// Turns away uploads that declare a length over the limit before any of the body is read.
+// Chunked uploads without a declared length are still caught while streaming.
+pub async fn check_content_length(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Result<Response, ApiError> {
+    let limit = state.config.max_upload_size;
+    let declared = request.headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok()?.parse::<u64>().ok());
+
+    if declared.is_some_and(|length| length > limit as u64) {
+        return Err(ApiError::PayloadTooLarge(limit));
+    }
+
+    Ok(next.run(request).await)
+}
+
+// A full disk is worth retrying later, unlike other write failures. The temp file of the
+// upload is removed either way.
+pub fn save_error(e: std::io::Error) -> ApiError {
+    match e.kind() {
+        std::io::ErrorKind::StorageFull | std::io::ErrorKind::QuotaExceeded => ApiError::StorageFull,
+        _ => ApiError::InternalError(format!("Failed to save file: {}", e)),
+    }
+}
+
+// Writes an upload's chunks, counting them against the request-wide upload limit and the
+// storage quota. Returns the hex encoded SHA-256 of the written data.
+async fn stream_to_file(
+    state: &AppState,
+    mut chunks: impl Stream<Item = Result<Bytes, ApiError>> + Unpin,
+    first_chunk: Bytes,
+    file: tokio::fs::File,
+    total_bytes: &mut usize,
+    reservation: &mut Reservation<'_>,
+) -> Result<String, ApiError> {
+    let max_upload_size = state.config.max_upload_size;
+    let mut writer = tokio::io::BufWriter::new(file);
+    let mut hasher = Sha256::new();
+    let mut chunk = Some(first_chunk);
+
+    while let Some(data) = chunk {
+        *total_bytes += data.len();
+        if *total_bytes > max_upload_size {
+            return Err(ApiError::PayloadTooLarge(max_upload_size));
+        }
+
+        reservation.grow(data.len() as u64)?;
+
+        hasher.update(&data);
+        writer.write_all(&data).await
+            .map_err(save_error)?;
+
+        chunk = chunks.next().await.transpose()?;
+    }
+
+    writer.flush().await
+        .map_err(save_error)?;
+
+    // Make sure the data is on disk before the file becomes visible under its final name
+    writer.into_inner().sync_all().await
+        .map_err(save_error)?;
+
+    Ok(hex::encode(hasher.finalize()))
+}
+
+// Skips over empty chunks, returns an empty chunk when there's no data at all
+async fn first_chunk(chunks: &mut (impl Stream<Item = Result<Bytes, ApiError>> + Unpin)) -> Result<Bytes, ApiError> {
+    while let Some(chunk) = chunks.next().await.transpose()? {
+        if !chunk.is_empty() {
+            return Ok(chunk);
+        }
+    }
+
+    Ok(Bytes::new())
+}
+
+// Bytes of an upload counted against --max-total-bytes while it's being written, so concurrent
+// uploads can't overshoot the quota together. They're handed back once the upload is either
+// stored, and counted for good, or abandoned.
+pub struct Reservation<'a> {
+    state: &'a AppState,
+    bytes: u64,
+}
+
+impl<'a> Reservation<'a> {
+    pub fn new(state: &'a AppState) -> Self {
+        Self { state, bytes: 0 }
+    }
+
+    pub fn grow(&mut self, bytes: u64) -> Result<(), ApiError> {
+        let Some(max_total_bytes) = self.state.config.max_total_bytes else {
+            return Ok(());
+        };
+        if !self.state.try_add_used_bytes(bytes, max_total_bytes) {
+            return Err(ApiError::InsufficientStorage);
+        }
+
+        self.bytes += bytes;
+        Ok(())
+    }
+}
+
+impl Drop for Reservation<'_> {
+    fn drop(&mut self) {
+        self.state.release_used_bytes(self.bytes);
+    }
+}
+
+// Removes a temp file unless kept. This also covers uploads whose request is dropped halfway,
+// e.g. by the request timeout.
+struct TempFile(Option<PathBuf>);
+
+impl TempFile {
+    fn keep(mut self) {
+        self.0 = None;
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        if let Some(path) = self.0.take() {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+// An upload written to a temp file in full, ready to be stored
+pub struct Assembled {
+    pub file_name: String,
+    pub content_type: Option<String>,
+    // Timestamped name in the day folder
+    pub stored_name: String,
+    pub timestamp: i64,
+    pub temp_path: PathBuf,
+    pub sha256: String,
+    pub size: u64,
+}
+
+// Today's folder of the requesting tenant, which every file of an upload request ends up in
+pub struct Destination<'a> {
+    state: &'a AppState,
+    tenant: &'a Tenant,
+    client: &'a ClientIp,
+    storage: Arc<dyn StorageBackend>,
+    date_dir: String,
+    upload_dir: PathBuf,
+}
+
+impl<'a> Destination<'a> {
+    pub async fn today(state: &'a AppState, tenant: &'a Tenant, client: &'a ClientIp) -> Result<Self, ApiError> {
+        // Create subfolder for each day
+        let date_dir = state.config.now().format("%Y-%m-%d").to_string();
+        let upload_dir = state.data_dir_for(tenant).join(&date_dir);
+        tokio::fs::create_dir_all(&upload_dir).await
+            .map_err(save_error)?;
+
+        Ok(Self {
+            state,
+            tenant,
+            client,
+            storage: state.storage_for(tenant),
+            date_dir,
+            upload_dir,
+        })
+    }
+
+    // Writes a small hidden file next to an upload through a temp file, like the upload itself
+    async fn save_sidecar(&self, key: &str, contents: Vec<u8>) -> std::io::Result<()> {
+        let name = key.rsplit('/').next().unwrap_or(key);
+        let temp_path = self.upload_dir.join(format!("{}.tmp", name));
+        let temp_file = TempFile(Some(temp_path.clone()));
+        tokio::fs::write(&temp_path, contents).await?;
+
+        let key = key.to_string();
+        storage::blocking(&self.storage, move |storage| storage.save(&key, &temp_path)).await?;
+        temp_file.keep();
+
+        Ok(())
+    }
+
+    // Picks the timestamped name a file is stored under, refusing names already taken
+    pub async fn stored_name(&self, file_name: &str) -> Result<(String, i64), ApiError> {
+        let sanitized_name = sanitize_filename(file_name)
+            .ok_or_else(|| ApiError::BadRequest(format!("Invalid file name: {}", file_name)))?;
+
+        let timestamp = chrono::Utc::now().timestamp();
+        let safe_file_name = format!("{}_{}", timestamp, sanitized_name);
+        // Only saves the upload from being streamed in vain, the backend refuses to replace it too
+        let key = format!("{}/{}", self.date_dir, safe_file_name);
+        if storage::blocking(&self.storage, move |storage| storage.exists(&key)).await.map_err(save_error)? {
+            return Err(ApiError::Conflict(safe_file_name));
+        }
+
+        Ok((safe_file_name, timestamp))
+    }
+
+    // Stores one file under a timestamped name, along with its sidecars and index entry.
+    // Returns how it is described in the response.
+    async fn store(
+        &self,
+        file_name: &str,
+        content_type: Option<String>,
+        first_chunk: Bytes,
+        chunks: impl Stream<Item = Result<Bytes, ApiError>> + Unpin,
+        total_bytes: &mut usize,
+    ) -> Result<Value, ApiError> {
+        let (safe_file_name, timestamp) = self.stored_name(file_name).await?;
+
+        // Write to a hidden temp file first and rename it once complete, so downloads never
+        // pick up a partially written upload
+        let temp_path = self.upload_dir.join(format!(".{}.tmp", safe_file_name));
+        let file = tokio::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&temp_path)
+            .await
+            .map_err(|e| match e.kind() {
+                std::io::ErrorKind::AlreadyExists => ApiError::Conflict(safe_file_name.clone()),
+                _ => save_error(e),
+            })?;
+        let temp_file = TempFile(Some(temp_path.clone()));
+
+        let saved_before = *total_bytes;
+        // Only released once the stored file is counted, the quota is never undercounted meanwhile
+        let mut reservation = Reservation::new(self.state);
+        let sha256 = stream_to_file(self.state, chunks, first_chunk, file, total_bytes, &mut reservation).await?;
+
+        let saved = self.commit(Assembled {
+            file_name: file_name.to_string(),
+            content_type,
+            stored_name: safe_file_name,
+            timestamp,
+            temp_path,
+            sha256,
+            size: (*total_bytes - saved_before) as u64,
+        })
+        .await?;
+        temp_file.keep();
+
+        Ok(saved)
+    }
+
+    // Moves a completely written file to its final name. Once that worked the rest can't fail
+    // the upload anymore, problems with sidecars and the index are only logged.
+    pub async fn commit(&self, file: Assembled) -> Result<Value, ApiError> {
+        let state = self.state;
+        let Assembled { file_name, content_type, stored_name, timestamp, temp_path, sha256, size } = file;
+
+        let stored_path = format!("{}/{}", self.date_dir, stored_name);
+        let saved = {
+            let (key, digest) = (stored_path.clone(), sha256.clone());
+            let dedup = state.config.dedup;
+            storage::blocking(&self.storage, move |storage| match dedup {
+                true => storage.save_deduplicated(&key, &temp_path, &digest),
+                false => storage.save(&key, &temp_path).map(|_| false),
+            })
+            .await
+        };
+        // Another upload of the same name in the same second got there first
+        let deduplicated = saved.map_err(|e| match e.kind() {
+            std::io::ErrorKind::AlreadyExists => ApiError::Conflict(stored_name.clone()),
+            _ => save_error(e),
+        })?;
+        state.add_used_bytes(size);
+        state.metrics.uploads.inc();
+        state.metrics.upload_size.observe(size as f64);
+
+        // Sidecars are only informational at this point, a missing checksum is recomputed on request
+        let checksum_key = files::checksum_key(&stored_path);
+        if let Err(e) = self.save_sidecar(&checksum_key, sha256.clone().into_bytes()).await {
+            tracing::error!(path = %stored_path, error = %e, "Failed to store checksum");
+        }
+
+        let meta = json!({
+            "original_name": file_name,
+            "content_type": content_type,
+            "size": size,
+            "sha256": sha256,
+            "uploaded_at": chrono::DateTime::from_timestamp(timestamp, 0).map(|t| t.to_rfc3339()),
+            "uploader_ip": self.client.0
+        });
+        if let Err(e) = self.save_sidecar(&files::meta_key(&stored_path), meta.to_string().into_bytes()).await {
+            tracing::error!(path = %stored_path, error = %e, "Failed to store metadata");
+        }
+
+        tracing::info!(
+            original = %file_name,
+            path = %self.tenant.scope(&stored_path),
+            size,
+            content_type = content_type.as_deref().unwrap_or("unknown"),
+            deduplicated,
+            client = %self.client,
+            "File uploaded"
+        );
+
+        #[cfg(feature = "sqlite")]
+        if let Some(index) = &state.index {
+            let record = crate::index::Record {
+                path: self.tenant.scope(&stored_path),
+                size,
+                sha256: Some(sha256.clone()),
+                content_type,
+                uploaded_at: timestamp,
+                original_name: Some(file_name.clone()),
+            };
+            if let Err(e) = index.insert(&record) {
+                tracing::error!(path = %stored_path, error = %e, "Failed to index upload");
+            }
+        }
+
+        Ok(json!({
+            "original_name": file_name,
+            "stored_path": stored_path,
+            "size": size,
+            "sha256": sha256,
+            "deduplicated": deduplicated
+        }))
+    }
+}
+
+// Records and announces the stored files, and builds the response shared by both upload routes.
+// Files the audit log couldn't record are removed again, the client is told the upload failed.
+pub async fn uploaded(destination: &Destination<'_>, saved_files: Vec<Value>) -> Result<Json<Value>, ApiError> {
+    let state = destination.state;
+    let paths: Vec<_> = saved_files.iter()
+        .filter_map(|file| file["stored_path"].as_str().map(str::to_string))
+        .collect();
+    if let Err(e) = audit::record(state, "upload", destination.client, destination.tenant, paths.clone()) {
+        for path in &paths {
+            if let Err(e) = files::remove_stored(state, destination.tenant, path).await {
+                tracing::error!(%path, error = %e, "Failed to remove unaudited upload");
+            }
+        }
+        return Err(e);
+    }
+
+    if let Some(webhook) = &state.webhook {
+        webhook.send(json!({
+            "event": "upload",
+            "timestamp": chrono::Utc::now().to_rfc3339(),
+            "files": saved_files
+        }));
+    }
+
+    Ok(Json(json!({
+        "status": "success",
+        "message": "File uploaded successfully",
+        "files": saved_files
+    })))
+}
+
+#[utoipa::path(
+    post,
+    path = "/upload",
+    description = "Stores every file field in today's folder. Bodies may be sent with Content-Encoding: gzip",
+    params(("Idempotency-Key" = Option<String>, Header, description = "Repeating a key returns the first upload's response")),
+    request_body(content = crate::openapi::UploadForm, content_type = "multipart/form-data"),
+    responses(
+        (status = 200, body = crate::openapi::Uploaded),
+        (status = 400, description = "Missing, empty or invalid file, or a field other than the upload field", body = crate::openapi::ErrorBody),
+        (status = 401, description = "Missing or invalid bearer token", body = crate::openapi::ErrorBody),
+        (status = 409, description = "A file with the same name was stored in the same second, or the Idempotency-Key is in use", body = crate::openapi::ErrorBody),
+        (status = 413, description = "Upload limit exceeded", body = crate::openapi::ErrorBody),
+        (status = 415, description = "Not multipart/form-data or unsupported Content-Encoding", body = crate::openapi::ErrorBody),
+        (status = 429, description = "Upload rate limit exceeded", body = crate::openapi::ErrorBody),
+        (status = 507, description = "Storage quota reached or disk full", body = crate::openapi::ErrorBody),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn upload_log(
+    State(state): State<Arc<AppState>>,
+    client: ClientIp,
+    tenant: Tenant,
+    multipart: Result<Multipart, MultipartRejection>,
+) -> Result<impl IntoResponse, ApiError> {
+    // The only way extraction fails is a missing or non-multipart Content-Type
+    let mut multipart = multipart
+        .map_err(|_| ApiError::UnsupportedMediaType("expected multipart/form-data".to_string()))?;
+    let max_upload_size = state.config.max_upload_size;
+    let mut total_bytes = 0;
+    let mut saved_files = Vec::new();
+    let mut empty_file = false;
+    let mut fields = 0;
+
+    let destination = Destination::today(&state, &tenant, &client).await?;
+    
+    // Iterate through file
+    while let Some(field) = multipart.next_field().await
+        .map_err(|e| multipart_error("Failed to read multipart field", e, max_upload_size))?
+    {
+        // Files saved before the limit is hit stay, like with any other failure mid-request
+        fields += 1;
+        if fields > state.config.max_files_per_request {
+            return Err(ApiError::BadRequest(format!(
+                "At most {} files can be uploaded at once",
+                state.config.max_files_per_request
+            )));
+        }
+
+        // Plain form values like a CSRF token travel alongside the file, they aren't uploads
+        let Some(file_name) = field.file_name().map(str::to_string) else {
+            continue;
+        };
+
+        // A misspelled field would otherwise be stored like any other
+        let field_name = field.name()
+            .ok_or_else(|| ApiError::BadRequest("Field name is missing".to_string()))?;
+        if field_name != state.config.upload_field {
+            return Err(ApiError::BadRequest(format!(
+                "Unexpected field {}, files are uploaded in {}",
+                field_name,
+                state.config.upload_field
+            )));
+        }
+
+        let content_type = field.content_type().map(str::to_string);
+        let mut chunks = field.map(|chunk| {
+            chunk.map_err(|e| multipart_error("Failed to read file data", e, max_upload_size))
+        });
+        
+        // Empty fields are usually forms submitted without a file, don't store them
+        let first_chunk = first_chunk(&mut chunks).await?;
+        if first_chunk.is_empty() {
+            empty_file = true;
+            continue;
+        }
+
+        let saved = destination.store(&file_name, content_type, first_chunk, chunks, &mut total_bytes).await?;
+        saved_files.push(saved);
+    }
+    
+    if saved_files.is_empty() && empty_file {
+        return Err(ApiError::BadRequest("empty file".to_string()));
+    }
+
+    if saved_files.is_empty() {
+        return Err(ApiError::BadRequest("No file was uploaded".to_string()));
+    }
+
+    uploaded(&destination, saved_files).await
+}
+
+// Takes the request body as the file's contents, for clients that can't encode multipart
+#[utoipa::path(
+    put,
+    path = "/upload/{filename}",
+    params(
+        ("filename" = String, Path, description = "Name the file is stored under, after the upload timestamp"),
+        ("Idempotency-Key" = Option<String>, Header, description = "Repeating a key returns the first upload's response"),
+    ),
+    request_body(content = Vec<u8>, content_type = "application/octet-stream"),
+    responses(
+        (status = 200, body = crate::openapi::Uploaded),
+        (status = 400, description = "Missing, empty or invalid file", body = crate::openapi::ErrorBody),
+        (status = 401, description = "Missing or invalid bearer token", body = crate::openapi::ErrorBody),
+        (status = 409, description = "A file with the same name was stored in the same second, or the Idempotency-Key is in use", body = crate::openapi::ErrorBody),
+        (status = 413, description = "Upload limit exceeded", body = crate::openapi::ErrorBody),
+        (status = 415, description = "Not multipart/form-data or unsupported Content-Encoding", body = crate::openapi::ErrorBody),
+        (status = 429, description = "Upload rate limit exceeded", body = crate::openapi::ErrorBody),
+        (status = 507, description = "Storage quota reached or disk full", body = crate::openapi::ErrorBody),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn put_log(
+    State(state): State<Arc<AppState>>,
+    client: ClientIp,
+    tenant: Tenant,
+    UrlPath(file_name): UrlPath<String>,
+    headers: HeaderMap,
+    body: Body,
+) -> Result<impl IntoResponse, ApiError> {
+    let content_type = headers.get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string);
+    let mut chunks = body.into_data_stream().map(|chunk| {
+        chunk.map_err(|e| ApiError::BadRequest(format!("Failed to read request body: {}", e)))
+    });
+
+    let first_chunk = first_chunk(&mut chunks).await?;
+    if first_chunk.is_empty() {
+        return Err(ApiError::BadRequest("empty file".to_string()));
+    }
+
+    let destination = Destination::today(&state, &tenant, &client).await?;
+    let mut total_bytes = 0;
+    let saved = destination.store(&file_name, content_type, first_chunk, chunks, &mut total_bytes).await?;
+
+    uploaded(&destination, vec![saved]).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn traversal_keeps_only_the_last_segment() {
+        assert_eq!(sanitize_filename("../../etc/passwd").as_deref(), Some("passwd"));
+        assert_eq!(sanitize_filename("..\\..\\windows\\system.ini").as_deref(), Some("system.ini"));
+        assert_eq!(sanitize_filename("/var/log/syslog").as_deref(), Some("syslog"));
+    }
+
+    #[test]
+    fn directories_are_dropped() {
+        assert_eq!(sanitize_filename("foo/bar.log").as_deref(), Some("bar.log"));
+    }
+
+    #[test]
+    fn hidden_names_and_control_characters_are_cleaned() {
+        assert_eq!(sanitize_filename(".bashrc").as_deref(), Some("bashrc"));
+        assert_eq!(sanitize_filename("app\0\n.log").as_deref(), Some("app.log"));
+    }
+
+    #[test]
+    fn long_names_are_capped() {
+        let name = sanitize_filename(&"a".repeat(1000)).unwrap();
+        assert_eq!(name.chars().count(), MAX_FILE_NAME_LEN);
+    }
+
+    #[test]
+    fn nothing_usable_left_is_none() {
+        for name in ["", "..", "../..", "foo/", "...", "\0\n", "   "] {
+            assert_eq!(sanitize_filename(name), None, "{:?}", name);
+        }
+    }
+
+    #[test]
+    fn reservations_share_the_quota() {
+        let app = crate::tests::TestApp::new(&["--max-upload-size", "1000", "--max-total-bytes", "1500"]);
+
+        let mut first = Reservation::new(&app.state);
+        assert!(first.grow(600).is_ok());
+        assert!(first.grow(400).is_ok());
+        let mut second = Reservation::new(&app.state);
+        assert!(matches!(second.grow(600), Err(ApiError::InsufficientStorage)));
+        assert!(second.grow(500).is_ok());
+        assert_eq!(app.state.used_bytes(), 1500);
+
+        drop(first);
+        assert_eq!(app.state.used_bytes(), 500);
+        drop(second);
+        assert_eq!(app.state.used_bytes(), 0);
+    }
+}
@@ -0,0 +1,220 @@
+use std::{
+    fs,
+    io::{self, BufWriter, Read},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use axum::{Json, extract::{Path as UrlPath, State}, response::IntoResponse};
+use flate2::read::GzDecoder;
+use serde_json::{Value, json};
+use sha2::{Digest, Sha256};
+
+use crate::{ApiError, AppState, archive::{self, ArchiveFormat, ArchiveOptions}, files::{self, DayRange}, retention, storage::{self, StorageBackend}, tenant::Tenant};
+
+// Whatever the archive turned out to hold, so corruption anywhere in it shows up
+#[derive(Default)]
+struct Report {
+    files: usize,
+    bytes: u64,
+    failures: Vec<Value>,
+}
+
+impl Report {
+    fn fail(&mut self, path: &str, error: impl ToString) {
+        self.failures.push(json!({ "path": path, "error": error.to_string() }));
+    }
+}
+
+// Reads `reader` to the end, returning its size and sha256. Zip entries check their CRC once
+// the last byte is read, gzip streams check theirs at the trailer.
+fn digest(mut reader: impl Read) -> io::Result<(u64, String)> {
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; 64 * 1024];
+    let mut size = 0;
+    loop {
+        let read = reader.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+        size += read as u64;
+    }
+
+    Ok((size, hex::encode(hasher.finalize())))
+}
+
+// Builds the day's zip the way a download would, then reads every entry back. Besides the
+// CRCs, each entry is checked against the checksum recorded when its file was uploaded, which
+// is what catches files that rotted on disk since.
+fn verify_day(state: &AppState, storage: &dyn StorageBackend, date: &str) -> io::Result<Report> {
+    let prefix = format!("{}/", date);
+    let mut entries: Vec<_> = storage.list()?
+        .into_iter()
+        .filter_map(|file| {
+            let name = file.key.strip_prefix(&prefix)?.to_string();
+            Some((file, name))
+        })
+        .collect();
+    if entries.is_empty() {
+        return Err(io::ErrorKind::NotFound.into());
+    }
+    entries.sort_by(|a, b| a.1.cmp(&b.1));
+
+    let options = ArchiveOptions {
+        format: ArchiveFormat::Zip,
+        range: DayRange::default(),
+        since: None,
+        compression_method: state.config.compression_method,
+        compression_level: state.config.compression_level,
+        compression_threads: state.config.compression_threads,
+        deterministic: false,
+        timezone: state.config.timezone,
+        file_mode: state.config.archive_file_mode,
+        password: None,
+        include: None,
+        exclude: None,
+    };
+
+    let mut report = Report::default();
+    let path = std::env::temp_dir().join(format!("eotwsink-verify-{}.zip", uuid::Uuid::new_v4().simple()));
+    let _temp = Temp(path.clone());
+
+    let built = fs::File::create(&path).and_then(|file| {
+        let mut writer = BufWriter::new(file);
+        archive::write_archive(&mut writer, storage, &entries, &options)?;
+        writer.into_inner().map_err(|e| e.into_error())?.sync_all()
+    });
+    if let Err(e) = built {
+        report.fail(date, format!("Failed to build archive: {}", e));
+        return Ok(report);
+    }
+
+    let mut zip = match fs::File::open(&path).map_err(zip::result::ZipError::Io).and_then(zip::ZipArchive::new) {
+        Ok(zip) => zip,
+        Err(e) => {
+            report.fail(date, format!("Failed to read archive: {}", e));
+            return Ok(report);
+        }
+    };
+
+    for (file, name) in &entries {
+        report.files += 1;
+
+        let entry = match zip.by_name(name) {
+            Ok(entry) => entry,
+            Err(e) => {
+                report.fail(&file.key, e);
+                continue;
+            }
+        };
+        match digest(entry) {
+            Ok((size, sha256)) => {
+                report.bytes += size;
+                if let Some(recorded) = files::recorded_checksum(storage, &file.key)
+                    && recorded != sha256
+                {
+                    report.fail(&file.key, format!("Checksum mismatch, recorded {} but read {}", recorded, sha256));
+                }
+            }
+            Err(e) => report.fail(&file.key, e),
+        }
+    }
+
+    Ok(report)
+}
+
+// Reads an archived day's tarball through to the gzip trailer
+fn verify_archived(path: &Path, date: &str) -> io::Result<Report> {
+    let mut report = Report::default();
+    let mut tar = tar::Archive::new(GzDecoder::new(fs::File::open(path)?));
+
+    let entries = match tar.entries() {
+        Ok(entries) => entries,
+        Err(e) => {
+            report.fail(date, e);
+            return Ok(report);
+        }
+    };
+    for entry in entries {
+        let mut entry = match entry {
+            Ok(entry) => entry,
+            // The rest of the stream can't be made sense of after a broken header
+            Err(e) => {
+                report.fail(date, e);
+                return Ok(report);
+            }
+        };
+
+        report.files += 1;
+        let name = entry.path().map(|path| format!("{}/{}", date, path.display())).unwrap_or_else(|_| date.to_string());
+        match digest(&mut entry) {
+            Ok((size, _)) => report.bytes += size,
+            Err(e) => report.fail(&name, e),
+        }
+    }
+
+    // Anything after the last entry, the gzip trailer included
+    if let Err(e) = digest(tar.into_inner()) {
+        report.fail(date, e);
+    }
+
+    Ok(report)
+}
+
+// Removes the built archive whichever way verification ends
+struct Temp(PathBuf);
+
+impl Drop for Temp {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/download/{date}/verify",
+    params(("date" = String, Path, description = "Day as YYYY-MM-DD")),
+    responses(
+        (status = 200, body = crate::openapi::Verification),
+        (status = 400, description = "Invalid date", body = crate::openapi::ErrorBody),
+        (status = 401, description = "Missing or invalid bearer token", body = crate::openapi::ErrorBody),
+        (status = 404, description = "No files stored that day", body = crate::openapi::ErrorBody),
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn verify(
+    State(state): State<Arc<AppState>>,
+    tenant: Tenant,
+    UrlPath(date): UrlPath<String>,
+) -> Result<impl IntoResponse, ApiError> {
+    files::parse_day_param(&date)?;
+
+    let archived = state.archive_dir_for(&tenant).and_then(|archive_dir| retention::archived_day(&archive_dir, &date));
+    let storage = state.storage_for(&tenant);
+    let (report, archived) = {
+        let (state, date) = (state.clone(), date.clone());
+        storage::blocking(&storage, move |storage| match verify_day(&state, storage, &date) {
+            // A day the retention sweep packed away is checked as the tarball it was packed into
+            Err(e) if e.kind() == io::ErrorKind::NotFound && let Some(path) = archived => {
+                verify_archived(&path, &date).map(|report| (report, true))
+            }
+            result => result.map(|report| (report, false)),
+        })
+        .await
+        .map_err(|e| storage::api_error("Failed to verify archive", e))?
+    };
+
+    if !report.failures.is_empty() {
+        tracing::warn!(%date, failures = report.failures.len(), "Archive verification failed");
+    }
+
+    Ok(Json(json!({
+        "date": date,
+        "archived": archived,
+        "files": report.files,
+        "total_bytes": report.bytes,
+        "ok": report.failures.is_empty(),
+        "failures": report.failures
+    })))
+}
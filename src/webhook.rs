@@ -0,0 +1,33 @@
+use std::time::Duration;
+
+use serde_json::Value;
+
+// Notifies a downstream service about new uploads
+pub struct Webhook {
+    url: String,
+    client: reqwest::Client,
+}
+
+impl Webhook {
+    pub fn new(url: String, timeout: Duration) -> reqwest::Result<Self> {
+        let client = reqwest::Client::builder()
+            .timeout(timeout)
+            .build()?;
+
+        Ok(Self { url, client })
+    }
+
+    // Posts `payload` on a separate task. The upload is already stored at this point, so a
+    // failing receiver is only logged.
+    pub fn send(&self, payload: Value) {
+        let request = self.client.post(&self.url).json(&payload);
+        let url = self.url.clone();
+
+        tokio::spawn(async move {
+            match request.send().await.and_then(|response| response.error_for_status()) {
+                Ok(response) => tracing::debug!(%url, status = response.status().as_u16(), "Webhook delivered"),
+                Err(e) => tracing::warn!(%url, error = %e, "Failed to deliver webhook"),
+            }
+        });
+    }
+}